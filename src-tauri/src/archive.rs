@@ -0,0 +1,147 @@
+//! Writing a backup into a single archive file instead of a folder tree.
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs;
+use std::io::{self, BufWriter};
+use std::path::{Component, Path};
+use tar::{EntryType, Header};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ArchiveFormat {
+    Zip,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    /// Parse the `archive` parameter, `None` for unknown formats
+    pub fn parse(format: &str) -> Option<Self> {
+        match format {
+            "zip" => Some(ArchiveFormat::Zip),
+            "targz" => Some(ArchiveFormat::TarGz),
+            _ => None,
+        }
+    }
+}
+
+/// An archive being filled with backed up files. Entries are streamed to
+/// disk as they are added.
+pub(crate) enum ArchiveWriter {
+    Zip(ZipWriter<BufWriter<fs::File>>),
+    TarGz(tar::Builder<GzEncoder<BufWriter<fs::File>>>),
+}
+
+fn zip_error(error: zip::result::ZipError) -> io::Error {
+    io::Error::other(error)
+}
+
+impl ArchiveWriter {
+    /// Create a new, empty archive at `path`, replacing any existing file
+    pub fn create(path: &Path, format: ArchiveFormat) -> io::Result<Self> {
+        let file = BufWriter::new(fs::File::create(path)?);
+        match format {
+            ArchiveFormat::Zip => Ok(ArchiveWriter::Zip(ZipWriter::new(file))),
+            ArchiveFormat::TarGz => {
+                let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+                // Links are only present when they should be stored as links
+                builder.follow_symlinks(false);
+                Ok(ArchiveWriter::TarGz(builder))
+            }
+        }
+    }
+
+    /// Add the contents of `source` under `name`, returning the bytes read
+    pub fn add_file(&mut self, name: &str, source: &Path) -> io::Result<u64> {
+        let mut reader = fs::File::open(source)?;
+        match self {
+            ArchiveWriter::Zip(zip) => {
+                let mut options = SimpleFileOptions::default()
+                    .compression_method(CompressionMethod::Deflated)
+                    .large_file(reader.metadata()?.len() >= u32::MAX as u64);
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::PermissionsExt;
+                    options = options.unix_permissions(reader.metadata()?.permissions().mode());
+                }
+                zip.start_file(name, options).map_err(zip_error)?;
+                io::copy(&mut reader, zip)
+            }
+            ArchiveWriter::TarGz(tar) => {
+                // The header takes the size, permissions and mtime from the file
+                let size = reader.metadata()?.len();
+                tar.append_file(name, &mut reader)?;
+                Ok(size)
+            }
+        }
+    }
+
+    /// Add a directory entry, taking its metadata from `source`
+    pub fn add_dir(&mut self, name: &str, source: &Path) -> io::Result<()> {
+        match self {
+            ArchiveWriter::Zip(zip) => zip
+                .add_directory(name, SimpleFileOptions::default())
+                .map_err(zip_error),
+            ArchiveWriter::TarGz(tar) => tar.append_dir(name, source),
+        }
+    }
+
+    /// Store a symbolic link pointing at `link_target`
+    pub fn add_symlink(&mut self, name: &str, link_target: &Path) -> io::Result<()> {
+        match self {
+            ArchiveWriter::Zip(zip) => zip
+                .add_symlink(name, link_target.to_string_lossy(), SimpleFileOptions::default())
+                .map_err(zip_error),
+            ArchiveWriter::TarGz(tar) => {
+                let mut header = Header::new_gnu();
+                header.set_entry_type(EntryType::Symlink);
+                header.set_size(0);
+                header.set_mode(0o777);
+                tar.append_link(&mut header, name, link_target)
+            }
+        }
+    }
+
+    /// Write the archive's index and flush it to disk
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            ArchiveWriter::Zip(zip) => {
+                let mut file = zip.finish().map_err(zip_error)?;
+                io::Write::flush(&mut file)
+            }
+            ArchiveWriter::TarGz(tar) => {
+                let mut file = tar.into_inner()?.finish()?;
+                io::Write::flush(&mut file)
+            }
+        }
+    }
+}
+
+/// Archive entry name for a relative path, always using `/` as separator
+pub(crate) fn entry_name(relative: &Path) -> String {
+    relative
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Human-readable size, e.g. `1.5 MB`
+pub(crate) fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
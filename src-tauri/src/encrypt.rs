@@ -0,0 +1,127 @@
+//! Encrypting backed up files with a key derived from a passphrase.
+//!
+//! An encrypted file starts with a header of the format `MAGIC`, the 16 byte
+//! Argon2 salt and the 19 byte stream nonce. The contents follow as
+//! XChaCha20-Poly1305 STREAM chunks of `CHUNK_SIZE` plaintext bytes, each with
+//! its 16 byte tag, so truncated or reordered files fail to decrypt.
+
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::stream::EncryptorBE32;
+use chacha20poly1305::aead::{KeyInit, OsRng};
+use chacha20poly1305::XChaCha20Poly1305;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Extension appended to the name of every encrypted file
+const EXTENSION: &str = "enc";
+
+/// Marks the file format and its version
+const MAGIC: &[u8; 8] = b"M4SENC01";
+
+const SALT_LEN: usize = 16;
+
+/// XChaCha20's 24 byte nonce minus the 5 bytes used by the STREAM counter
+const NONCE_LEN: usize = 19;
+
+/// Plaintext bytes per encrypted chunk
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Encrypts files with one key for the whole backup. Deriving the key is
+/// deliberately slow, so it's done once and its salt stored in every file.
+pub(crate) struct FileEncryptor {
+    cipher: XChaCha20Poly1305,
+    salt: [u8; SALT_LEN],
+}
+
+impl FileEncryptor {
+    /// Derive a key from `passphrase` with a fresh random salt
+    pub fn new(passphrase: &str) -> Result<Self, String> {
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+
+        let mut key = [0; 32];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| format!("Failed to derive encryption key: {}", e))?;
+
+        Ok(FileEncryptor {
+            cipher: XChaCha20Poly1305::new(&key.into()),
+            salt,
+        })
+    }
+
+    /// Write an encrypted copy of `source` to `dest` and return the number of
+    /// plaintext bytes. Like `fs::copy`, the source's permissions are carried
+    /// over.
+    pub fn encrypt_file(&self, source: &Path, dest: &Path) -> io::Result<u64> {
+        let mut reader = fs::File::open(source)?;
+        let permissions = reader.metadata()?.permissions();
+        let mut writer = io::BufWriter::new(fs::File::create(dest)?);
+
+        let mut nonce = [0; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&self.salt)?;
+        writer.write_all(&nonce)?;
+
+        let mut encryptor = EncryptorBE32::from_aead(self.cipher.clone(), &nonce.into());
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let mut next = vec![0; CHUNK_SIZE];
+        let mut len = read_full(&mut reader, &mut chunk)?;
+        let mut total = len as u64;
+
+        // Every chunk but the last is full, so read ahead to find the last one
+        loop {
+            let next_len = if len == CHUNK_SIZE {
+                read_full(&mut reader, &mut next)?
+            } else {
+                0
+            };
+            if next_len == 0 {
+                let sealed = encryptor.encrypt_last(&chunk[..len]).map_err(encryption_error)?;
+                writer.write_all(&sealed)?;
+                break;
+            }
+
+            let sealed = encryptor.encrypt_next(&chunk[..len]).map_err(encryption_error)?;
+            writer.write_all(&sealed)?;
+            std::mem::swap(&mut chunk, &mut next);
+            len = next_len;
+            total += len as u64;
+        }
+
+        writer.flush()?;
+        drop(writer);
+        fs::set_permissions(dest, permissions)?;
+        Ok(total)
+    }
+}
+
+/// `path` with the encrypted file extension appended
+pub(crate) fn encrypted_name(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".");
+    name.push(EXTENSION);
+    PathBuf::from(name)
+}
+
+fn encryption_error(_: chacha20poly1305::aead::Error) -> io::Error {
+    io::Error::other("Encryption failed")
+}
+
+/// Fill `buffer` as far as the reader allows, returning how much was read
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
@@ -0,0 +1,102 @@
+//! Content hashes remembered between runs, so files that haven't changed
+//! don't have to be read again to be compared or verified.
+//!
+//! The cache is a JSON file in the target mapping absolute paths to the size,
+//! modification time and BLAKE3 hash a file had when it was last hashed. An
+//! entry is only used while the file still has that size and time.
+
+use filetime::FileTime;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::hash_file;
+
+/// File name of the cache inside the target directory
+pub(crate) const HASH_CACHE_FILENAME: &str = ".m4ssc0py-hashes.json";
+
+#[derive(Clone, Serialize, Deserialize)]
+struct CachedHash {
+    size: u64,
+    /// Modification time in seconds since the Unix epoch and nanoseconds
+    modified: (i64, u32),
+    hash: String,
+}
+
+pub(crate) struct HashCache {
+    path: PathBuf,
+    entries: Mutex<HashMap<PathBuf, CachedHash>>,
+}
+
+impl HashCache {
+    /// Open the cache at `path`. A missing or unreadable cache starts empty.
+    pub fn load(path: &Path) -> Self {
+        let entries = fs::File::open(path)
+            .ok()
+            .and_then(|file| serde_json::from_reader(BufReader::new(file)).ok())
+            .unwrap_or_default();
+        HashCache {
+            path: path.to_path_buf(),
+            entries: Mutex::new(entries),
+        }
+    }
+
+    /// Hash of `file`, read from the cache if the file is unchanged since it
+    /// was stored and computed and stored otherwise
+    pub fn hash(&self, file: &Path) -> io::Result<blake3::Hash> {
+        let metadata = fs::metadata(file)?;
+        let cached = self.entries().get(file).cloned();
+        if let Some(cached) = cached.filter(|cached| is_current(cached, &metadata)) {
+            if let Ok(hash) = blake3::Hash::from_hex(&cached.hash) {
+                return Ok(hash);
+            }
+        }
+
+        let hash = hash_file(file)?;
+        self.insert(file, &metadata, hash);
+        Ok(hash)
+    }
+
+    /// Store the hash of a file that was just read anyway, such as when it
+    /// was copied or verified
+    pub fn remember(&self, file: &Path, hash: blake3::Hash) {
+        if let Ok(metadata) = fs::metadata(file) {
+            self.insert(file, &metadata, hash);
+        }
+    }
+
+    /// Write the cache back, leaving out files that no longer exist
+    pub fn save(&self) -> io::Result<()> {
+        let mut entries = self.entries();
+        entries.retain(|file, _| file.exists());
+        let writer = BufWriter::new(fs::File::create(&self.path)?);
+        serde_json::to_writer(writer, &*entries)?;
+        Ok(())
+    }
+
+    fn insert(&self, file: &Path, metadata: &fs::Metadata, hash: blake3::Hash) {
+        let entry = CachedHash {
+            size: metadata.len(),
+            modified: modified(metadata),
+            hash: hash.to_hex().to_string(),
+        };
+        self.entries().insert(file.to_path_buf(), entry);
+    }
+
+    fn entries(&self) -> MutexGuard<'_, HashMap<PathBuf, CachedHash>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn modified(metadata: &fs::Metadata) -> (i64, u32) {
+    let time = FileTime::from_last_modification_time(metadata);
+    (time.unix_seconds(), time.nanoseconds())
+}
+
+/// Whether a file still has the size and modification time of its entry
+fn is_current(cached: &CachedHash, metadata: &fs::Metadata) -> bool {
+    cached.size == metadata.len() && cached.modified == modified(metadata)
+}
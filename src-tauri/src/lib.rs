@@ -1,9 +1,136 @@
-use globset::{Glob, GlobSet, GlobSetBuilder};
+mod archive;
+mod encrypt;
+mod hash_cache;
+mod logging;
+mod manifest;
+mod report;
+mod sftp;
+#[cfg(test)]
+mod tests;
+mod throttle;
+mod xattrs;
+
+use filetime::FileTime;
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
 use ignore::WalkBuilder;
-use serde::Serialize;
+use regex::{RegexSet, RegexSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque};
+use std::ffi::OsStr;
 use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::ControlFlow;
 use std::path::{Path, PathBuf};
-use tauri::{AppHandle, Emitter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter, Manager, Runtime, State};
+use unicode_normalization::UnicodeNormalization;
+
+use archive::{ArchiveFormat, ArchiveWriter};
+use encrypt::FileEncryptor;
+use hash_cache::{HashCache, HASH_CACHE_FILENAME};
+use logging::BackupLog;
+use manifest::{Manifest, ManifestEntry};
+use sftp::{Credentials, RemoteTarget, SftpSession};
+use throttle::{ChunkedCopy, RateLimiter, DEFAULT_BUFFER_SIZE};
+
+/// Shared state used to control an in-progress backup from other commands
+struct BackupControl {
+    cancelled: AtomicBool,
+    /// Answers to `backup-conflict` events, sent by `resolve_conflict`
+    decisions: Mutex<Sender<ConflictDecision>>,
+    pending_decisions: Mutex<Receiver<ConflictDecision>>,
+    /// Answers to `backup-review` events, sent by `approve_file` and
+    /// `reject_file`
+    reviews: Mutex<Sender<ReviewDecision>>,
+    pending_reviews: Mutex<Receiver<ReviewDecision>>,
+    /// Errors tests make chosen copies fail with, kept across runs
+    #[cfg(test)]
+    faults: Mutex<Option<FaultHook>>,
+}
+
+/// Picks the error, if any, a copy of the given source file fails with on
+/// the given attempt, counting retries from 0
+#[cfg(test)]
+type FaultHook = Arc<dyn Fn(&Path, u32) -> Option<std::io::ErrorKind> + Send + Sync>;
+
+impl Default for BackupControl {
+    fn default() -> Self {
+        let (decisions, pending_decisions) = mpsc::channel();
+        let (reviews, pending_reviews) = mpsc::channel();
+        BackupControl {
+            cancelled: AtomicBool::new(false),
+            decisions: Mutex::new(decisions),
+            pending_decisions: Mutex::new(pending_decisions),
+            reviews: Mutex::new(reviews),
+            pending_reviews: Mutex::new(pending_reviews),
+            #[cfg(test)]
+            faults: Mutex::new(None),
+        }
+    }
+}
+
+impl BackupControl {
+    /// Prepare for a new run, so a cancel or an answer meant for a previous
+    /// one doesn't affect it
+    fn reset(&self) {
+        self.cancelled.store(false, Ordering::SeqCst);
+        let pending = self.pending_decisions.lock().unwrap_or_else(|e| e.into_inner());
+        while pending.try_recv().is_ok() {}
+        let pending = self.pending_reviews.lock().unwrap_or_else(|e| e.into_inner());
+        while pending.try_recv().is_ok() {}
+    }
+
+    /// Error the hook set by a test picks for this copy of `source`
+    #[cfg(test)]
+    fn injected_fault(&self, source: &Path, attempt: u32) -> Option<std::io::Error> {
+        let faults = self.faults.lock().unwrap_or_else(|e| e.into_inner());
+        let kind = faults.as_ref()?(source, attempt)?;
+        Some(std::io::Error::new(kind, "injected fault"))
+    }
+
+    fn send_review(&self, decision: ReviewDecision) -> Result<(), String> {
+        self.reviews
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .send(decision)
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Whether the user wants a file copied in review mode
+struct ReviewDecision {
+    approved: bool,
+    /// Decide every later file of the run the same way
+    rest: bool,
+}
+
+/// How the user chose to resolve a collision in prompt mode
+struct ConflictDecision {
+    /// The collision mode to apply: `overwrite`, `skip` or `rename`
+    mode: &'static str,
+    /// Apply it to every later conflict of the run as well
+    all: bool,
+}
+
+impl ConflictDecision {
+    fn parse(decision: &str) -> Option<Self> {
+        let (mode, all) = match decision {
+            "overwrite" => ("overwrite", false),
+            "skip" => ("skip", false),
+            "rename" => ("rename", false),
+            "overwrite-all" => ("overwrite", true),
+            "skip-all" => ("skip", true),
+            _ => return None,
+        };
+        Some(ConflictDecision { mode, all })
+    }
+}
 
 #[derive(Clone, Serialize)]
 struct BackupProgress {
@@ -11,14 +138,154 @@ struct BackupProgress {
     copied_count: u64,
     skipped_count: u64,
     total_count: u64,
+    total_bytes: u64,
+    copied_bytes: u64,
+    /// True while the current file's checksum is being compared
+    verifying: bool,
+    /// Recent transfer rate, 0 until enough data has been copied to tell
+    bytes_per_second: f64,
+    eta_seconds: Option<u64>,
+    /// How far along the backup is, from 0 to 100. Based on bytes when the
+    /// files have any content, otherwise on the number of files handled.
+    percent: f64,
+    /// Position of the event in its run, starting at 1, so updates that
+    /// arrive late can be told apart from newer ones
+    seq: u64,
+}
+
+/// Running totals while the sources are being scanned
+#[derive(Clone, Serialize)]
+struct BackupCounting {
+    found_count: u64,
+    found_bytes: u64,
+}
+
+#[derive(Clone, Serialize)]
+struct BackupStart {
+    total_count: u64,
+    total_bytes: u64,
+    /// Absolute path of the target directory
+    target: String,
+    source_count: usize,
 }
 
 #[derive(Clone, Serialize)]
 struct BackupComplete {
     success: bool,
+    copied_count: u64,
+    /// Sum of `skipped_collision` and `excluded_filter`
+    skipped_count: u64,
+    /// Files skipped because of what was already at their destination
+    skipped_collision: u64,
+    /// Files left out by the blacklist, whitelist, size or date filters.
+    /// Entries hidden by ignore files never reach the filters and aren't
+    /// counted.
+    excluded_filter: u64,
+    /// Directories left out because they couldn't be read, usually for lack
+    /// of permission
+    skipped_unreadable: u64,
+    /// Files not copied in fill mode because the target ran out of space
+    left_out_count: u64,
+    message: String,
+    errors: Vec<BackupError>,
+    /// Of `copied_count`, files that replaced an older version at their
+    /// destination
+    updated_count: u64,
+    deleted_count: u64,
+    moved_count: u64,
+    reflinked_count: u64,
+    deduped_count: u64,
+    /// Files hardlinked to their unchanged copy in `link_dest`
+    linked_count: u64,
+    /// Destination of every copied file, filled only when requested
+    copied_files: Vec<String>,
+    /// Source path and size of the largest copied files, largest first
+    top_files: Vec<(String, u64)>,
+    /// What each source path contributed, in the order of `source_paths`
+    per_source: Vec<SourceStats>,
+    /// Time from the start of copying, after counting, until the end
+    elapsed_ms: u64,
+    /// Bytes copied per second over `elapsed_ms`
+    average_bytes_per_second: f64,
+    /// Follows the `seq` of the run's last `backup-progress` event
+    seq: u64,
+}
+
+/// Totals of a single source path in `BackupComplete`
+#[derive(Clone, Serialize)]
+struct SourceStats {
+    path: String,
+    copied: u64,
+    /// Files skipped because of what was already at their destination
+    skipped: u64,
+    bytes: u64,
+    errors: u64,
+}
+
+/// Sent in prompt mode when a file's destination exists, answered with
+/// `resolve_conflict`
+#[derive(Clone, Serialize)]
+struct BackupConflict {
+    source: String,
+    dest: String,
+}
+
+/// Sent in review mode before each file is copied, answered with
+/// `approve_file` or `reject_file`
+#[derive(Clone, Serialize)]
+struct BackupReview {
+    source: String,
+    /// Path relative to its source
+    path: String,
+    size: u64,
+}
+
+/// Sent when a backup stops early because it reached `max_errors`
+#[derive(Clone, Serialize)]
+struct BackupAborted {
+    error_count: u64,
+    max_errors: u64,
+}
+
+#[derive(Clone, Serialize)]
+struct BackupCancelled {
     copied_count: u64,
     skipped_count: u64,
+}
+
+#[derive(Clone, Serialize)]
+struct BackupDeleted {
+    path: String,
+}
+
+#[derive(Clone, Serialize)]
+struct BackupPruned {
+    path: String,
+}
+
+#[derive(Clone, Serialize)]
+struct BackupRetry {
+    file: String,
+    /// Number of the retry about to be made, starting at 1
+    attempt: u32,
+    message: String,
+}
+
+/// Sent once every file below a source directory has been handled
+#[derive(Clone, Serialize)]
+struct BackupDirComplete {
+    /// Path of the directory relative to its source
+    path: String,
+    /// Files anywhere below the directory, including its subdirectories
+    file_count: u64,
+    /// Position of the directory's source in `source_paths`
+    source_index: usize,
+}
+
+#[derive(Clone, Serialize)]
+struct BackupWarning {
     message: String,
+    file: Option<String>,
 }
 
 #[derive(Clone, Serialize)]
@@ -27,49 +294,556 @@ struct BackupError {
     file: Option<String>,
 }
 
-/// Build a GlobSet from a list of patterns
-fn build_glob_set(patterns: &[String]) -> GlobSet {
+/// A source with its own filters, which replace the global ones when given
+#[derive(Deserialize)]
+struct SourceSpec {
+    path: String,
+    blacklist: Option<Vec<String>>,
+    whitelist: Option<Vec<String>>,
+}
+
+/// Build a GlobSet from a list of patterns, along with a warning for every
+/// pattern that was reinterpreted or dropped
+fn build_glob_set(patterns: &[String], case_insensitive: bool) -> (GlobSet, Vec<String>) {
+    let patterns: Vec<(&str, bool)> = patterns.iter().map(|p| (p.as_str(), false)).collect();
+    let mut warnings = Vec::new();
+    let (set, _) = build_indexed_glob_set(&patterns, case_insensitive, &mut warnings);
+    (set, warnings)
+}
+
+/// Build a GlobSet from a list of patterns, each with whether it's anchored
+/// to the source root, also returning the index into `patterns` of every
+/// glob in the set, since invalid patterns may be dropped.
+///
+/// Brace alternatives like `*.{jpg,png}` are expanded, and `*` may match
+/// across `/` except in anchored patterns. A pattern that isn't a valid glob,
+/// such as one with an unclosed or nested brace, is matched literally as a
+/// file or folder name instead, so `{draft` only excludes entries named
+/// exactly `{draft`. Each such fallback adds a message to `warnings`.
+fn build_indexed_glob_set(
+    patterns: &[(&str, bool)],
+    case_insensitive: bool,
+    warnings: &mut Vec<String>,
+) -> (GlobSet, Vec<usize>) {
     let mut builder = GlobSetBuilder::new();
+    let mut indices = Vec::new();
 
-    for pattern in patterns {
-        match Glob::new(pattern) {
-            Ok(glob) => {
-                builder.add(glob);
-            }
-            Err(_) => {
-                // If pattern is invalid as glob, treat it as literal match
-                if let Ok(glob) = Glob::new(&format!("**/{}", pattern)) {
-                    builder.add(glob);
-                }
+    for (index, &(pattern, anchored)) in patterns.iter().enumerate() {
+        let (glob, error) = compile_pattern(pattern, case_insensitive, anchored);
+        match (&glob, error) {
+            (_, None) => {}
+            (Some(_), Some(e)) => warnings.push(format!(
+                "Pattern '{}' is not a valid glob ({}), matching it as a literal name",
+                pattern, e
+            )),
+            (None, Some(e)) => warnings.push(format!(
+                "Pattern '{}' is not a valid glob ({}) and was ignored",
+                pattern, e
+            )),
+        }
+        if let Some(glob) = glob {
+            builder.add(glob);
+            indices.push(index);
+        }
+    }
+
+    match builder.build() {
+        Ok(set) => (set, indices),
+        Err(e) => {
+            warnings.push(format!("Patterns were ignored: {}", e));
+            (GlobSet::empty(), Vec::new())
+        }
+    }
+}
+
+/// Compile a single pattern, returning the glob to match it with, if any,
+/// and why the pattern itself isn't a valid glob. An `anchored` pattern is
+/// matched against whole relative paths, so its `*` stays within one folder.
+fn compile_pattern(
+    pattern: &str,
+    case_insensitive: bool,
+    anchored: bool,
+) -> (Option<Glob>, Option<String>) {
+    let glob = |pattern: &str| {
+        GlobBuilder::new(pattern)
+            .case_insensitive(case_insensitive)
+            .literal_separator(anchored)
+            .build()
+    };
+
+    match glob(pattern) {
+        Ok(glob) => (Some(glob), None),
+        Err(e) => {
+            // If pattern is invalid as glob, treat it as literal match.
+            // It has to be escaped, or the same syntax error recurs.
+            let escaped = globset::escape(pattern);
+            let literal = if anchored { escaped } else { format!("**/{}", escaped) };
+            (glob(&literal).ok(), Some(e.kind().to_string()))
+        }
+    }
+}
+
+/// Compiled blacklist patterns.
+///
+/// A pattern starting with `!` re-includes paths excluded by an earlier
+/// pattern, like in .gitignore. When several patterns match a path the last
+/// one in the list wins, so `*.log, !important.log` keeps `important.log`
+/// while `!important.log, *.log` excludes it.
+///
+/// A pattern starting with `/` is anchored to the source root: `build`
+/// matches a `build` folder at any level, `/build` only the one directly
+/// inside the source, along with everything in it. In anchored patterns `*`
+/// doesn't match `/`, so `/*.log` leaves out `a.log` but not `src/a.log`.
+struct Blacklist {
+    exclude: GlobSet,
+    /// Position in the original pattern list of each glob in `exclude`
+    exclude_order: Vec<usize>,
+    /// Whether each glob in `exclude` is anchored to the source root
+    exclude_anchored: Vec<bool>,
+    include: GlobSet,
+    /// Position in the original pattern list of each glob in `include`
+    include_order: Vec<usize>,
+    /// Whether each glob in `include` is anchored to the source root
+    include_anchored: Vec<bool>,
+    /// Regular expressions excluding paths regardless of `!` patterns
+    regexes: Option<RegexSet>,
+    /// The original patterns, with whether each has matched a path yet
+    patterns: Vec<String>,
+    used: Vec<AtomicBool>,
+}
+
+impl Blacklist {
+    /// Compile the patterns, adding a message to `warnings` for each one that
+    /// was reinterpreted or dropped
+    fn new(patterns: &[String], case_insensitive: bool, warnings: &mut Vec<String>) -> Self {
+        let mut excludes = Vec::new();
+        let mut includes = Vec::new();
+        for (position, pattern) in patterns.iter().enumerate() {
+            match pattern.strip_prefix('!') {
+                Some(rest) => includes.push((position, rest)),
+                None => excludes.push((position, pattern.as_str())),
             }
         }
+
+        let mut build = |entries: &[(usize, &str)]| {
+            let patterns: Vec<(&str, bool)> = entries
+                .iter()
+                .map(|(_, p)| p.strip_prefix('/').map_or((*p, false), |p| (p, true)))
+                .collect();
+            let (set, indices) = build_indexed_glob_set(&patterns, case_insensitive, warnings);
+            let order = indices.iter().map(|&i| entries[i].0).collect::<Vec<_>>();
+            let anchored = indices.iter().map(|&i| patterns[i].1).collect();
+            (set, order, anchored)
+        };
+
+        let (exclude, exclude_order, exclude_anchored) = build(&excludes);
+        let (include, include_order, include_anchored) = build(&includes);
+
+        Blacklist {
+            exclude,
+            exclude_order,
+            exclude_anchored,
+            include,
+            include_order,
+            include_anchored,
+            regexes: None,
+            patterns: patterns.to_vec(),
+            used: patterns.iter().map(|_| AtomicBool::new(false)).collect(),
+        }
+    }
+
+    /// Each pattern that could be compiled, in list order, with whether it
+    /// has matched a path so far. A `!` pattern only counts once it brought
+    /// back an excluded path.
+    fn pattern_use(&self) -> Vec<(&str, bool)> {
+        let mut positions: Vec<usize> =
+            self.exclude_order.iter().chain(&self.include_order).copied().collect();
+        positions.sort_unstable();
+        positions.dedup();
+        positions
+            .into_iter()
+            .map(|i| (self.patterns[i].as_str(), self.used[i].load(Ordering::Relaxed)))
+            .collect()
     }
 
-    builder.build().unwrap_or_else(|_| GlobSet::empty())
+    /// Also exclude paths matching any of the regular expressions, failing on
+    /// the first one that doesn't compile
+    fn with_regexes(mut self, patterns: &[String], case_insensitive: bool) -> Result<Self, String> {
+        if patterns.is_empty() {
+            return Ok(self);
+        }
+
+        let regexes = RegexSetBuilder::new(patterns)
+            .case_insensitive(case_insensitive)
+            .build()
+            .map_err(|e| format!("Invalid regex pattern: {}", e))?;
+        self.regexes = Some(regexes);
+        Ok(self)
+    }
 }
 
-/// Check if a path should be blacklisted using glob patterns
-fn is_blacklisted(relative_path: &Path, glob_set: &GlobSet) -> bool {
-    // Check if the full path matches
-    if glob_set.is_match(relative_path) {
-        return true;
+/// Position of the last pattern in `set` that matches, trying anchored
+/// patterns against `prefixes` and all others against `candidates`. Every
+/// matching pattern is marked in `used`.
+fn last_match(
+    set: &GlobSet,
+    order: &[usize],
+    anchored: &[bool],
+    used: &[AtomicBool],
+    candidates: &[&Path],
+    prefixes: &[&Path],
+) -> Option<usize> {
+    let mut matches = Vec::new();
+    let mut last = None;
+    let tries = candidates.iter().map(|c| (c, false)).chain(prefixes.iter().map(|p| (p, true)));
+    for (candidate, for_anchored) in tries {
+        set.matches_into(candidate, &mut matches);
+        for &i in matches.iter().filter(|&&i| anchored[i] == for_anchored) {
+            used[order[i]].store(true, Ordering::Relaxed);
+            last = last.max(Some(order[i]));
+        }
     }
+    last
+}
 
-    // Check if any component matches (for simple patterns like "node_modules")
+/// Check if a path should be blacklisted using glob patterns. Patterns are
+/// matched against the full path and against every component (for simple
+/// patterns like "node_modules"). Anchored patterns are only matched against
+/// the full path and the folders leading to it, so they also cover the
+/// contents of a matching folder. The full path always uses `/` separators,
+/// on Windows too, and regular expressions are matched the same way.
+fn is_blacklisted(relative_path: &Path, blacklist: &Blacklist) -> bool {
+    // Patterns are written with `/`, so Windows paths are matched the same way
+    let full_path = archive::entry_name(relative_path);
+    let mut candidates = vec![Path::new(&full_path)];
     for component in relative_path.components() {
         if let std::path::Component::Normal(name) = component {
-            if glob_set.is_match(name) {
-                return true;
+            candidates.push(Path::new(name));
+        }
+    }
+    // `a`, `a/b`, ... up to the full path
+    let prefixes: Vec<&Path> = full_path
+        .match_indices('/')
+        .map(|(i, _)| Path::new(&full_path[..i]))
+        .chain([Path::new(&full_path)])
+        .collect();
+
+    // Every glob is tried, even when a regex matches, to know which
+    // patterns are in use
+    let excluded = last_match(
+        &blacklist.exclude,
+        &blacklist.exclude_order,
+        &blacklist.exclude_anchored,
+        &blacklist.used,
+        &candidates,
+        &prefixes,
+    );
+    let glob_excluded = match excluded {
+        Some(excluded_at) => last_match(
+            &blacklist.include,
+            &blacklist.include_order,
+            &blacklist.include_anchored,
+            &blacklist.used,
+            &candidates,
+            &prefixes,
+        )
+        .is_none_or(|included_at| included_at < excluded_at),
+        None => false,
+    };
+
+    glob_excluded
+        || blacklist.regexes.as_ref().is_some_and(|regexes| {
+            regexes.is_match(&full_path)
+                || candidates[1..].iter().any(|c| regexes.is_match(&c.to_string_lossy()))
+        })
+}
+
+/// Blacklist patterns that none of `filters` matched against any path, in
+/// the order they were first seen
+fn unused_patterns<'a>(filters: impl Iterator<Item = &'a FileFilter>) -> Vec<&'a str> {
+    let mut unused: Vec<(&str, bool)> = Vec::new();
+    for (pattern, used) in filters.flat_map(|filter| filter.blacklist.pattern_use()) {
+        match unused.iter_mut().find(|(seen, _)| *seen == pattern) {
+            Some((_, seen_unused)) => *seen_unused &= !used,
+            None => unused.push((pattern, !used)),
+        }
+    }
+    unused.into_iter().filter(|(_, unused)| *unused).map(|(pattern, _)| pattern).collect()
+}
+
+/// Blacklist and whitelist filters shared by the counting and copying passes
+struct FileFilter {
+    blacklist: Blacklist,
+    /// When present, only files matching one of these patterns are copied
+    whitelist: Option<GlobSet>,
+    /// Inclusive file size bounds in bytes
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    /// Modification time window in Unix epoch seconds, from `modified_after`
+    /// inclusive up to `modified_before` exclusive
+    modified_after: Option<i64>,
+    modified_before: Option<i64>,
+    /// Lowercased endings, such as `.log`, of file names that are left out
+    excluded_extensions: Vec<String>,
+    /// Patterns matched against the absolute path of each entry
+    absolute_blacklist: Option<GlobSet>,
+    /// Problems with the patterns found while compiling them
+    warnings: Vec<String>,
+}
+
+impl FileFilter {
+    fn new(blacklist: &[String], whitelist: &[String], case_insensitive: bool) -> Self {
+        let mut warnings = Vec::new();
+        let blacklist = Blacklist::new(blacklist, case_insensitive, &mut warnings);
+        let whitelist = if whitelist.is_empty() {
+            None
+        } else {
+            let (set, whitelist_warnings) = build_glob_set(whitelist, case_insensitive);
+            warnings.extend(whitelist_warnings);
+            Some(set)
+        };
+
+        FileFilter {
+            blacklist,
+            whitelist,
+            min_size: None,
+            max_size: None,
+            modified_after: None,
+            modified_before: None,
+            excluded_extensions: Vec::new(),
+            absolute_blacklist: None,
+            warnings,
+        }
+    }
+
+    /// Add regular expression patterns to the blacklist
+    fn with_regex_blacklist(
+        mut self,
+        patterns: &[String],
+        case_insensitive: bool,
+    ) -> Result<Self, String> {
+        self.blacklist = self.blacklist.with_regexes(patterns, case_insensitive)?;
+        Ok(self)
+    }
+
+    /// Only accept files whose size lies within `min_size..=max_size`
+    fn with_size_limits(mut self, min_size: Option<u64>, max_size: Option<u64>) -> Self {
+        self.min_size = min_size;
+        self.max_size = max_size;
+        self
+    }
+
+    /// Only accept files modified within `modified_after..modified_before`
+    fn with_modified_window(mut self, after: Option<i64>, before: Option<i64>) -> Self {
+        self.modified_after = after;
+        self.modified_before = before;
+        self
+    }
+
+    /// Leave out files with any of these extensions, given with or without
+    /// their leading dot, whatever their case
+    fn with_excluded_extensions(mut self, extensions: &[String]) -> Self {
+        self.excluded_extensions = extensions
+            .iter()
+            .map(|extension| extension.trim().trim_start_matches('.').to_lowercase())
+            .filter(|extension| !extension.is_empty())
+            .map(|extension| format!(".{}", extension))
+            .collect();
+        self
+    }
+
+    /// Also leave out entries whose absolute path matches one of these
+    /// patterns, such as `/home/*/.cache` or `C:/Windows/Temp`
+    fn with_absolute_blacklist(mut self, patterns: &[String], case_insensitive: bool) -> Self {
+        if !patterns.is_empty() {
+            let (set, warnings) = build_glob_set(patterns, case_insensitive);
+            self.warnings.extend(warnings);
+            self.absolute_blacklist = Some(set);
+        }
+        self
+    }
+
+    /// Check whether `path` or one of the folders containing it matches the
+    /// absolute blacklist. Relative sources are resolved against the working
+    /// directory first.
+    fn is_excluded_absolute(&self, path: &Path) -> bool {
+        let Some(set) = &self.absolute_blacklist else { return false };
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        path.ancestors().any(|ancestor| {
+            // Patterns are written with `/`, so Windows paths are matched the same way
+            let name = ancestor.to_string_lossy();
+            if cfg!(windows) {
+                set.is_match(name.replace('\\', "/"))
+            } else {
+                set.is_match(name.as_ref())
+            }
+        })
+    }
+
+    /// Check whether a file's name ends in one of the excluded extensions
+    fn has_excluded_extension(&self, relative_path: &Path) -> bool {
+        if self.excluded_extensions.is_empty() {
+            return false;
+        }
+        let Some(name) = relative_path.file_name() else { return false };
+        let name = name.to_string_lossy().to_lowercase();
+        self.excluded_extensions
+            .iter()
+            .any(|extension| name.len() > extension.len() && name.ends_with(extension.as_str()))
+    }
+
+    /// Check whether a file's modification time is within the configured
+    /// window. A file whose time can't be read only passes without a window.
+    fn accepts_modified(&self, modified: Option<SystemTime>) -> bool {
+        if self.modified_after.is_none() && self.modified_before.is_none() {
+            return true;
+        }
+        let Some(modified) = modified else { return false };
+
+        let seconds = match modified.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs() as i64),
+        };
+        self.modified_after.is_none_or(|after| seconds >= after)
+            && self.modified_before.is_none_or(|before| seconds < before)
+    }
+
+    /// Check whether a file's size is within the configured bounds
+    fn accepts_size(&self, size: u64) -> bool {
+        self.min_size.is_none_or(|min| size >= min) && self.max_size.is_none_or(|max| size <= max)
+    }
+
+    /// Check whether a file or directory is excluded by the blacklist
+    fn is_excluded(&self, relative_path: &Path) -> bool {
+        is_blacklisted(relative_path, &self.blacklist)
+    }
+
+    /// Check whether a file matches the whitelist, against either its full
+    /// relative path (with `/` separators) or its file name, and doesn't have
+    /// an excluded extension. Directories are never checked so that matching
+    /// files deep in the tree are still reached.
+    fn is_wanted(&self, relative_path: &Path) -> bool {
+        if self.has_excluded_extension(relative_path) {
+            return false;
+        }
+        match &self.whitelist {
+            Some(whitelist) => {
+                whitelist.is_match(archive::entry_name(relative_path))
+                    || relative_path
+                        .file_name()
+                        .is_some_and(|name| whitelist.is_match(name))
             }
+            None => true,
+        }
+    }
+
+    /// Check whether a file should be copied
+    fn accepts_file(&self, relative_path: &Path) -> bool {
+        !self.is_excluded(relative_path) && self.is_wanted(relative_path)
+    }
+}
+
+/// Check whether `dir` treats file names differing only in case as the same,
+/// by creating a probe file and looking it up in upper case. In dry-run mode
+/// nothing is created, so the platform's usual behavior is assumed.
+fn is_case_insensitive_dir(dir: &Path, dry_run: bool) -> bool {
+    if dry_run {
+        return cfg!(any(windows, target_os = "macos"));
+    }
+
+    let probe = dir.join(".m4ssc0py-case-probe");
+    if fs::OpenOptions::new().write(true).create_new(true).open(&probe).is_err() {
+        return false;
+    }
+    let insensitive = dir.join(".M4SSC0PY-CASE-PROBE").exists();
+    let _ = fs::remove_file(&probe);
+    insensitive
+}
+
+/// Key under which a case-insensitive file system stores `path`
+fn folded_path(path: &Path) -> PathBuf {
+    PathBuf::from(path.to_string_lossy().to_lowercase())
+}
+
+/// Find an available filename by adding _1, _2, etc. suffix, or by filling
+/// in `template` as described at `validate_rename_template`.
+///
+/// The chosen name is claimed by atomically creating an empty placeholder
+/// file there, so concurrent workers can never pick the same one. The caller
+/// is expected to overwrite or remove it. In dry-run mode nothing is created.
+fn find_available_name(path: &Path, template: Option<&str>, dry_run: bool) -> PathBuf {
+    if dry_run {
+        return find_name_where(path, template, |candidate| candidate.exists());
+    }
+
+    find_name_where(path, template, |candidate| {
+        match fs::OpenOptions::new().write(true).create_new(true).open(long_path(candidate)) {
+            Ok(_) => false,
+            Err(e) => e.kind() == std::io::ErrorKind::AlreadyExists,
         }
+    })
+}
+
+/// Suffix used for renamed files when no template is given
+const DEFAULT_RENAME_TEMPLATE: &str = "{stem}_{n}{ext}";
+
+/// Check that a rename template can produce a new name on every attempt. It
+/// may use `{stem}`, `{ext}` (including its dot), `{n}` for a counter
+/// starting at 1 and `{timestamp}` for the current local time, and needs at
+/// least one of the last two.
+fn validate_rename_template(template: &str) -> Result<(), String> {
+    if !template.contains("{n}") && !template.contains("{timestamp}") {
+        return Err(format!("Rename template must contain {{n}} or {{timestamp}}: {}", template));
+    }
+    if template.contains(['/', '\\']) {
+        return Err(format!("Rename template can't contain a path separator: {}", template));
     }
+    Ok(())
+}
+
+/// Check the file names given in `name_overrides` and key them by the
+/// canonical path of their source file, so any spelling of it matches
+fn parse_name_overrides(
+    overrides: &HashMap<String, String>,
+) -> Result<HashMap<PathBuf, PathBuf>, String> {
+    overrides
+        .iter()
+        .map(|(source, name)| {
+            let single_name = matches!(
+                Path::new(name).components().collect::<Vec<_>>()[..],
+                [std::path::Component::Normal(part)] if part == name.as_str()
+            );
+            if !single_name {
+                return Err(format!("Name override must be a plain file name: {}", name));
+            }
+            let source = Path::new(source)
+                .canonicalize()
+                .map_err(|_| format!("Name override for a file that does not exist: {}", source))?;
+            Ok((source, PathBuf::from(name)))
+        })
+        .collect()
+}
 
-    false
+/// The name picked in `overrides` for a file, if it's a single-file source or
+/// lies directly inside its source
+fn name_override(
+    overrides: &HashMap<PathBuf, PathBuf>,
+    source: &Path,
+    relative: &Path,
+) -> Option<PathBuf> {
+    if overrides.is_empty() || relative.components().count() != 1 {
+        return None;
+    }
+    overrides.get(&source.canonicalize().ok()?).cloned()
 }
 
-/// Find an available filename by adding _1, _2, etc. suffix
-fn find_available_name(path: &Path) -> PathBuf {
-    if !path.exists() {
+/// Like `find_available_name`, but with a custom check for taken names
+fn find_name_where(
+    path: &Path,
+    template: Option<&str>,
+    is_taken: impl Fn(&Path) -> bool,
+) -> PathBuf {
+    if !is_taken(path) {
         return path.to_path_buf();
     }
 
@@ -83,12 +857,28 @@ fn find_available_name(path: &Path) -> PathBuf {
         .map(|e| format!(".{}", e.to_string_lossy()))
         .unwrap_or_default();
     let parent = path.parent().unwrap_or(Path::new(""));
+    let template = template.unwrap_or(DEFAULT_RENAME_TEMPLATE);
+    let timestamp = chrono::Local::now().format(VERSION_FORMAT).to_string();
 
     let mut counter = 1;
     loop {
-        let new_name = format!("{}_{}{}", stem, counter, ext);
+        // A template without a counter gets one once its first name is taken
+        let template = if counter > 1 && !template.contains("{n}") {
+            Cow::Owned(if template.contains("{ext}") {
+                template.replacen("{ext}", "_{n}{ext}", 1)
+            } else {
+                format!("{}_{{n}}", template)
+            })
+        } else {
+            Cow::Borrowed(template)
+        };
+        let new_name = template
+            .replace("{stem}", &stem)
+            .replace("{ext}", &ext)
+            .replace("{timestamp}", &timestamp)
+            .replace("{n}", &counter.to_string());
         let new_path = parent.join(new_name);
-        if !new_path.exists() {
+        if !is_taken(&new_path) {
             return new_path;
         }
         counter += 1;
@@ -99,281 +889,4697 @@ fn find_available_name(path: &Path) -> PathBuf {
     }
 }
 
-/// Recursively copy directories/files using the `ignore` crate for fast traversal
-/// and glob-based blacklist filtering.
-#[tauri::command]
-async fn backup_directory(
-    app: AppHandle,
-    source_paths: Vec<String>,
-    target_path: String,
-    blacklist: Vec<String>,
-    respect_gitignore: bool,
-    include_source_dir: bool,
-    collision_mode: String,
-) -> Result<BackupComplete, String> {
-    let target = Path::new(&target_path);
+/// Give an absolute path the `\\?\` extended-length prefix, so Windows
+/// accepts it even when longer than 260 characters
+#[cfg(windows)]
+fn long_path(path: &Path) -> Cow<'_, Path> {
+    use std::ffi::OsString;
+    use std::path::{Component, Prefix};
 
-    // Validate we have sources
-    if source_paths.is_empty() {
-        return Err("No source paths provided".to_string());
+    let mut components = path.components();
+    let Some(Component::Prefix(prefix)) = components.next() else {
+        return Cow::Borrowed(path);
+    };
+    if !path.is_absolute() {
+        return Cow::Borrowed(path);
     }
 
-    // Validate all sources exist
-    for source_path in &source_paths {
-        let source = Path::new(source_path);
-        if !source.exists() {
-            return Err(format!("Source path does not exist: {}", source_path));
+    let mut long = match prefix.kind() {
+        Prefix::Disk(_) => {
+            let mut long = OsString::from(r"\\?\");
+            long.push(prefix.as_os_str());
+            long
         }
-    }
+        Prefix::UNC(server, share) => {
+            let mut long = OsString::from(r"\\?\UNC\");
+            long.push(server);
+            long.push(r"\");
+            long.push(share);
+            long
+        }
+        // Already verbatim, or a device path
+        _ => return Cow::Borrowed(path),
+    };
 
-    // Create target directory if it doesn't exist
-    if let Err(e) = fs::create_dir_all(target) {
-        return Err(format!("Failed to create target directory: {}", e));
+    // Windows doesn't normalize verbatim paths, so resolve `.` and `..` and
+    // switch to backslashes here
+    let mut parts = Vec::new();
+    for component in components {
+        match component {
+            Component::Normal(part) => parts.push(part),
+            Component::ParentDir => {
+                parts.pop();
+            }
+            _ => {}
+        }
+    }
+    if parts.is_empty() {
+        long.push(r"\");
+    }
+    for part in parts {
+        long.push(r"\");
+        long.push(part);
     }
 
-    // Build glob set from blacklist patterns
-    let glob_set = build_glob_set(&blacklist);
+    Cow::Owned(PathBuf::from(long))
+}
 
-    // First pass: count total files for progress calculation
-    let total_count = count_files_multi(&source_paths, &glob_set, respect_gitignore);
+#[cfg(not(windows))]
+fn long_path(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
 
-    let mut copied_count: u64 = 0;
-    let mut skipped_count: u64 = 0;
-    let mut errors: Vec<String> = Vec::new();
+/// Create a directory and its parents. Does nothing in dry-run mode.
+fn create_dir(path: &Path, dry_run: bool) -> std::io::Result<()> {
+    if dry_run {
+        return Ok(());
+    }
+    fs::create_dir_all(long_path(path))
+}
 
-    // Process each source path
-    for source_path in &source_paths {
-        let source = Path::new(source_path);
+/// Write a file at `dest` through `write`. An existing file is replaced by
+/// renaming a new one over it instead of being written to, so other hardlinks
+/// to it keep their content and the name is never free in between.
+fn replace_file<T>(
+    dest: &Path,
+    write: impl FnOnce(&Path) -> std::io::Result<T>,
+) -> std::io::Result<T> {
+    if fs::symlink_metadata(dest).is_err() {
+        return write(dest);
+    }
 
-        if source.is_file() {
-            // Handle single file
-            if let Some(file_name) = source.file_name() {
-                let mut dest_path = target.join(file_name);
+    let temp = temp_path(dest);
+    // Left behind by an interrupted run
+    let _ = fs::remove_file(&temp);
+    let result = write(&temp).and_then(|value| fs::rename(&temp, dest).map(|()| value));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
 
-                // Check blacklist
-                if is_blacklisted(Path::new(file_name), &glob_set) {
-                    continue;
-                }
+/// Copy a single file and return the number of bytes written, along with
+/// whether it was reflinked instead of copied byte by byte. When `chunked`
+/// sets a bandwidth limit or timeout, the data is copied through it.
+/// With `hash` set, the bytes are hashed as they are copied and the hash is
+/// returned too; reflinked files aren't read, so they come without one.
+/// In dry-run mode nothing is written and the source size is returned instead.
+fn copy_file(
+    source: &Path,
+    dest: &Path,
+    dry_run: bool,
+    prefer_reflink: bool,
+    hash: bool,
+    chunked: &ChunkedCopy,
+) -> std::io::Result<(u64, bool, Option<blake3::Hash>)> {
+    let source = &*long_path(source);
+    let dest = &*long_path(dest);
 
-                // Handle collision
-                if dest_path.exists() {
-                    match collision_mode.as_str() {
-                        "skip" => {
-                            skipped_count += 1;
-                            continue;
-                        }
-                        "rename" => {
-                            dest_path = find_available_name(&dest_path);
-                        }
-                        _ => {} // overwrite
-                    }
-                }
+    let size = fs::metadata(source)?.len();
+    if dry_run {
+        return Ok((size, false, None));
+    }
 
-                match fs::copy(source, &dest_path) {
-                    Ok(_) => {
-                        copied_count += 1;
-                        let _ = app.emit(
-                            "backup-progress",
-                            BackupProgress {
-                                current_file: file_name.to_string_lossy().to_string(),
-                                copied_count,
-                                skipped_count,
-                                total_count,
-                            },
-                        );
-                    }
-                    Err(e) => {
-                        errors.push(format!("Failed to copy {:?}: {}", source, e));
-                        let _ = app.emit(
-                            "backup-error",
-                            BackupError {
-                                message: e.to_string(),
-                                file: Some(source_path.clone()),
-                            },
-                        );
-                    }
-                }
-            }
-        } else if source.is_dir() {
-            // Handle directory
-            let effective_target = if include_source_dir {
-                if let Some(source_name) = source.file_name() {
-                    target.join(source_name)
-                } else {
-                    target.to_path_buf()
-                }
+    // Hashing needs the bytes to pass through the chunked copy loop
+    let use_chunked = hash || chunked.is_needed(size);
+    replace_file(dest, |dest| {
+        let copy_chunked = || {
+            chunked
+                .copy(source, dest, hash, 0)
+                .map(|(bytes, hash)| (bytes, false, hash))
+        };
+        if !prefer_reflink {
+            return if use_chunked {
+                copy_chunked()
             } else {
-                target.to_path_buf()
+                fs::copy(source, dest).map(|bytes| (bytes, false, None))
             };
+        }
 
-            if let Err(e) = fs::create_dir_all(&effective_target) {
-                errors.push(format!("Failed to create target dir {:?}: {}", effective_target, e));
-                continue;
+        // Reflinks don't transfer any data, so only the fallback is throttled
+        if use_chunked {
+            if reflink_copy::reflink(source, dest).is_ok() {
+                return fs::metadata(dest).map(|m| (m.len(), true, None));
             }
+            return copy_chunked();
+        }
+
+        match reflink_copy::reflink_or_copy(source, dest)? {
+            Some(bytes) => Ok((bytes, false, None)),
+            None => fs::metadata(dest).map(|m| (m.len(), true, None)),
+        }
+    })
+}
 
-            // Build the walker
-            let mut builder = WalkBuilder::new(source);
-            builder
-                .hidden(false)
-                .git_ignore(respect_gitignore)
-                .git_global(false)
-                .git_exclude(respect_gitignore);
+/// Recreate the symbolic link `source` at `dest`, replacing an existing entry
+#[cfg(unix)]
+fn copy_symlink(source: &Path, dest: &Path, dry_run: bool) -> Result<u64, String> {
+    let link_target = fs::read_link(source).map_err(|e| e.to_string())?;
+    if dry_run {
+        return Ok(0);
+    }
+    replace_file(dest, |dest| std::os::unix::fs::symlink(&link_target, dest))
+        .map_err(|e| e.to_string())?;
+    Ok(0)
+}
 
-            let walker = builder.build();
+#[cfg(not(unix))]
+fn copy_symlink(_source: &Path, _dest: &Path, _dry_run: bool) -> Result<u64, String> {
+    Err("Symbolic links can't be recreated on this platform".to_string())
+}
 
-            for entry in walker {
-                match entry {
-                    Ok(dir_entry) => {
-                        let path = dir_entry.path();
+/// Copy the modification time of `source` onto `dest`
+fn copy_mtime(source: &Path, dest: &Path) -> std::io::Result<()> {
+    let metadata = fs::metadata(source)?;
+    filetime::set_file_mtime(dest, FileTime::from_last_modification_time(&metadata))
+}
 
-                        // Calculate relative path from source
-                        let relative_path = match path.strip_prefix(source) {
-                            Ok(p) => p,
-                            Err(_) => continue,
-                        };
+/// Whether `copy_creation_time` can set creation times on this platform
+const CREATION_TIME_SUPPORTED: bool = cfg!(any(windows, target_os = "macos"));
 
-                        // Skip if blacklisted
-                        if is_blacklisted(relative_path, &glob_set) {
-                            continue;
-                        }
+/// Copy the creation time of `source` onto `dest`
+#[cfg(any(windows, target_os = "macos"))]
+fn copy_creation_time(source: &Path, dest: &Path) -> std::io::Result<()> {
+    #[cfg(target_os = "macos")]
+    use std::os::macos::fs::FileTimesExt;
+    #[cfg(windows)]
+    use std::os::windows::fs::FileTimesExt;
 
-                        let mut dest_path = effective_target.join(relative_path);
-
-                        if path.is_dir() {
-                            if let Err(e) = fs::create_dir_all(&dest_path) {
-                                errors.push(format!("Failed to create dir {:?}: {}", dest_path, e));
-                                let _ = app.emit(
-                                    "backup-error",
-                                    BackupError {
-                                        message: e.to_string(),
-                                        file: Some(path.to_string_lossy().to_string()),
-                                    },
-                                );
-                            }
-                        } else if path.is_file() {
-                            // Ensure parent directory exists
-                            if let Some(parent) = dest_path.parent() {
-                                if let Err(e) = fs::create_dir_all(parent) {
-                                    errors.push(format!("Failed to create parent dir {:?}: {}", parent, e));
-                                    continue;
-                                }
-                            }
+    let created = fs::metadata(source)?.created()?;
+    let file = fs::OpenOptions::new().write(true).open(long_path(dest))?;
+    file.set_times(fs::FileTimes::new().set_created(created))
+}
 
-                            // Handle collision
-                            if dest_path.exists() {
-                                match collision_mode.as_str() {
-                                    "skip" => {
-                                        skipped_count += 1;
-                                        continue;
-                                    }
-                                    "rename" => {
-                                        dest_path = find_available_name(&dest_path);
-                                    }
-                                    _ => {} // overwrite
-                                }
-                            }
+/// Linux and most other systems don't let creation times be set
+#[cfg(not(any(windows, target_os = "macos")))]
+fn copy_creation_time(_source: &Path, _dest: &Path) -> std::io::Result<()> {
+    Ok(())
+}
 
-                            // Copy the file
-                            match fs::copy(path, &dest_path) {
-                                Ok(_) => {
-                                    copied_count += 1;
-                                    let _ = app.emit(
-                                        "backup-progress",
-                                        BackupProgress {
-                                            current_file: relative_path.to_string_lossy().to_string(),
-                                            copied_count,
-                                            skipped_count,
-                                            total_count,
-                                        },
-                                    );
-                                }
-                                Err(e) => {
-                                    errors.push(format!("Failed to copy {:?}: {}", path, e));
-                                    let _ = app.emit(
-                                        "backup-error",
-                                        BackupError {
-                                            message: e.to_string(),
-                                            file: Some(path.to_string_lossy().to_string()),
-                                        },
-                                    );
-                                }
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        errors.push(format!("Walker error: {}", e));
-                    }
-                }
-            }
-        }
+/// Copy the permission bits of `source` onto `dest`. On Windows only the
+/// readonly flag carries over.
+fn copy_permissions(source: &Path, dest: &Path) -> std::io::Result<()> {
+    let metadata = fs::metadata(source)?;
+    fs::set_permissions(dest, metadata.permissions())
+}
+
+/// Whether a file shares its content with other hardlinks, which writing to
+/// it in place would change too. Always false where the count is unknown.
+fn has_other_links(metadata: &fs::Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        metadata.nlink() > 1
     }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        false
+    }
+}
 
-    let message = if errors.is_empty() {
-        if skipped_count > 0 {
-            format!("Copied {} files, skipped {}", copied_count, skipped_count)
-        } else {
-            format!("Successfully copied {} files", copied_count)
-        }
+/// Give the owner write access to an existing file, so it can be replaced.
+/// Links are left alone, as they are replaced without being written to, and
+/// so are hardlinked files, whose permissions the other links share.
+fn make_writable(path: &Path) -> std::io::Result<()> {
+    let metadata = fs::symlink_metadata(long_path(path))?;
+    let mut permissions = metadata.permissions();
+    if !metadata.is_file() || !permissions.readonly() || has_other_links(&metadata) {
+        return Ok(());
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        permissions.set_mode(permissions.mode() | 0o200);
+    }
+    // Windows only has the readonly flag, so this doesn't widen access
+    #[cfg(not(unix))]
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(false);
+
+    fs::set_permissions(long_path(path), permissions)
+}
+
+/// Check whether `dest` has the same size and modification time as `source`.
+/// This is only reliable if earlier runs preserved timestamps, otherwise every
+/// previously copied file will look changed.
+fn is_unchanged(source: &Path, dest: &Path) -> bool {
+    match (fs::metadata(source), fs::metadata(dest)) {
+        (Ok(src), Ok(dst)) => {
+            src.len() == dst.len()
+                && matches!((src.modified(), dst.modified()), (Ok(a), Ok(b)) if a == b)
+        }
+        _ => false,
+    }
+}
+
+/// Compute the BLAKE3 hash of a file's contents
+fn hash_file(path: &Path) -> std::io::Result<blake3::Hash> {
+    let mut hasher = blake3::Hasher::new();
+    std::io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize())
+}
+
+/// Check whether `dest` holds the same bytes as `source`, comparing sizes
+/// before hashing both with `hash`. A file that can't be read counts as
+/// different.
+fn has_same_content(
+    source: &Path,
+    dest: &Path,
+    hash: &dyn Fn(&Path) -> std::io::Result<blake3::Hash>,
+) -> bool {
+    match (fs::metadata(source), fs::metadata(dest)) {
+        (Ok(src), Ok(dst)) if src.len() == dst.len() => {
+            matches!((hash(source), hash(dest)), (Ok(a), Ok(b)) if a == b)
+        }
+        _ => false,
+    }
+}
+
+/// Check whether `source` was modified strictly after `dest`.
+/// Returns `None` if either timestamp can't be read.
+fn is_newer(source: &Path, dest: &Path) -> Option<bool> {
+    let src = fs::metadata(source).and_then(|m| m.modified()).ok()?;
+    let dst = fs::metadata(dest).and_then(|m| m.modified()).ok()?;
+    Some(src > dst)
+}
+
+/// Like `is_newer`, for modification times in Unix seconds
+fn is_newer_mtime(source: Option<u64>, dest: Option<u64>) -> Option<bool> {
+    match (source, dest) {
+        (Some(source), Some(dest)) => Some(source > dest),
+        _ => None,
+    }
+}
+
+/// Bytes per second over a whole run, 0 if it took no measurable time
+fn average_rate(bytes: u64, elapsed: Duration) -> f64 {
+    let seconds = elapsed.as_secs_f64();
+    if seconds > 0.0 {
+        bytes as f64 / seconds
+    } else {
+        0.0
+    }
+}
+
+/// Format a duration like `3m12s` or `1h05m00s`
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m{:02}s", m, s),
+        (h, m, s) => format!("{}h{:02}m{:02}s", h, m, s),
+    }
+}
+
+/// How far back the transfer rate looks
+const RATE_WINDOW: Duration = Duration::from_secs(5);
+
+/// Shortest span of samples the transfer rate is computed from
+const MIN_RATE_SPAN: Duration = Duration::from_millis(500);
+
+/// Rolling window of `(time, copied bytes)` samples for estimating speed
+#[derive(Default)]
+struct TransferRate {
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl TransferRate {
+    /// Record the byte count at `now` and return the rate in bytes per
+    /// second over the window, or `None` if it spans too little time yet
+    fn sample(&mut self, now: Instant, copied_bytes: u64) -> Option<f64> {
+        self.samples.push_back((now, copied_bytes));
+        while self.samples.len() > 2
+            && self.samples.get(1).is_some_and(|(time, _)| now.duration_since(*time) >= RATE_WINDOW)
+        {
+            self.samples.pop_front();
+        }
+
+        let (start, start_bytes) = *self.samples.front()?;
+        let span = now.duration_since(start);
+        if span < MIN_RATE_SPAN {
+            return None;
+        }
+        Some(copied_bytes.saturating_sub(start_bytes) as f64 / span.as_secs_f64())
+    }
+}
+
+/// Number of files found between two `backup-counting` events
+const COUNTING_INTERVAL: u64 = 5000;
+
+/// How often a worker waiting for a conflict answer checks for cancellation
+const CONFLICT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Maximum number of errors returned in `BackupComplete`
+const MAX_REPORTED_ERRORS: usize = 1000;
+
+/// Number of largest copied files listed in `BackupComplete` by default
+const DEFAULT_TOP_FILES: usize = 10;
+
+/// Counters and errors shared between copy workers
+#[derive(Default)]
+struct BackupStats {
+    copied_count: u64,
+    copied_bytes: u64,
+    /// Files skipped because of their destination, shown in progress events
+    skipped_count: u64,
+    /// Files left out by the filters
+    excluded_count: u64,
+    /// Directories the walk wasn't allowed to read
+    unreadable_count: u64,
+    /// The first `MAX_REPORTED_ERRORS` errors; `error_count` has the real total
+    errors: Vec<BackupError>,
+    error_count: u64,
+    cancelled: bool,
+    /// Whether the run was stopped for reaching `max_errors`
+    aborted: bool,
+    /// Copied files that replaced an existing destination
+    updated_count: u64,
+    deleted_count: u64,
+    /// Source files removed after being copied in move mode
+    moved_count: u64,
+    /// Copied files that were cloned copy-on-write
+    reflinked_count: u64,
+    /// Files hardlinked to an identical copy instead of copied
+    deduped_count: u64,
+    /// Files hardlinked to their copy in an earlier backup
+    linked_count: u64,
+    /// First destination of each content hash, filled only in dedupe mode
+    dedupe_index: HashMap<blake3::Hash, PathBuf>,
+    /// First destination of each canonical source path, filled only when
+    /// deduplicating symlinks
+    real_path_index: HashMap<PathBuf, PathBuf>,
+    /// Source directories seen in move mode, removed at the end if empty
+    source_dirs: Vec<PathBuf>,
+    /// Source and destination of each target directory, tracked only when
+    /// preserving timestamps
+    created_dirs: Vec<(PathBuf, PathBuf)>,
+    /// Counters of each source path, by its position in `source_paths`
+    per_source: Vec<SourceTally>,
+    /// When the last progress event was emitted
+    last_progress: Option<Instant>,
+    /// `seq` of the last progress event
+    progress_seq: u64,
+    /// File from the most recent progress update that was throttled away
+    pending_progress: Option<String>,
+    rate: TransferRate,
+    /// Copied files, collected only when a manifest is requested
+    manifest: Vec<ManifestEntry>,
+    /// Destination paths of copied files, collected only when requested
+    copied_files: Vec<String>,
+    /// The largest files copied so far by size and source path, with the
+    /// smallest of them on top so it can be replaced
+    largest_files: BinaryHeap<Reverse<(u64, String)>>,
+    /// Answer to a prompt that applies to every later conflict
+    conflict_answer: Option<&'static str>,
+    /// Answer to a review that applies to every later file
+    review_answer: Option<bool>,
+    /// Destination paths produced by this run, tracked only in mirror mode
+    expected_paths: HashSet<PathBuf>,
+    /// Relative paths of the entries whose name was rewritten, before and
+    /// after
+    renamed_entries: Vec<(String, String)>,
+    /// Whether fill mode found the target full, so no more files are copied
+    target_full: bool,
+    /// Files not copied once the target was full
+    left_out: Vec<String>,
+    /// Source directories with files still waiting to be copied, by source
+    /// index and relative path
+    open_dirs: HashMap<(usize, PathBuf), DirProgress>,
+}
+
+/// How far the files below one source directory have got
+#[derive(Default)]
+struct DirProgress {
+    /// Files found below the directory so far
+    files: u64,
+    /// Copy jobs queued for those files that haven't finished
+    pending: u64,
+    /// Whether the walk has moved past the directory, so no more files can
+    /// turn up in it
+    walked: bool,
+}
+
+impl BackupStats {
+    fn record_error(&mut self, error: BackupError) {
+        tracing::error!(file = error.file.as_deref(), "{}", error.message);
+        self.error_count += 1;
+        if self.errors.len() < MAX_REPORTED_ERRORS {
+            self.errors.push(error);
+        }
+    }
+
+    /// Remember a copied file if it's among the `limit` largest so far
+    fn track_largest(&mut self, source: &Path, size: u64, limit: usize) {
+        if limit == 0 {
+            return;
+        }
+        if self.largest_files.len() >= limit {
+            match self.largest_files.peek() {
+                Some(Reverse((smallest, _))) if *smallest < size => {
+                    self.largest_files.pop();
+                }
+                _ => return,
+            }
+        }
+        self.largest_files.push(Reverse((size, source.to_string_lossy().to_string())));
+    }
+
+    /// Counters of the source at `source_index`
+    fn source(&mut self, source_index: usize) -> &mut SourceTally {
+        if self.per_source.len() <= source_index {
+            self.per_source.resize_with(source_index + 1, SourceTally::default);
+        }
+        &mut self.per_source[source_index]
+    }
+
+    fn count_source(&mut self, source_index: usize, bytes: u64) {
+        let source = self.source(source_index);
+        source.copied += 1;
+        source.bytes += bytes;
+    }
+}
+
+/// Running counters of one source path, see `SourceStats`
+#[derive(Clone, Default)]
+struct SourceTally {
+    copied: u64,
+    skipped: u64,
+    bytes: u64,
+    errors: u64,
+}
+
+impl SourceTally {
+    fn into_stats(self, path: &str) -> SourceStats {
+        SourceStats {
+            path: path.to_string(),
+            copied: self.copied,
+            skipped: self.skipped,
+            bytes: self.bytes,
+            errors: self.errors,
+        }
+    }
+}
+
+/// A single file queued for copying
+struct CopyJob {
+    source: PathBuf,
+    dest: PathBuf,
+    /// Path shown to the user in progress events
+    display_name: String,
+    /// Recreate the link itself instead of copying what it points to
+    symlink: bool,
+    /// Position of the file's source in `source_paths`
+    source_index: usize,
+    /// Directory of the file relative to its source, `None` when it isn't
+    /// tracked for `backup-dir-complete` events
+    dir: Option<PathBuf>,
+}
+
+/// Result of successfully copying one file
+struct CopiedFile {
+    bytes: u64,
+    /// Whether the data was cloned copy-on-write instead of rewritten
+    reflinked: bool,
+    /// Whether the file was hardlinked to an identical copy
+    deduped: bool,
+    /// Content hash, if one was computed while copying or verifying
+    checksum: Option<blake3::Hash>,
+}
+
+/// Settings and shared state for one backup run
+struct BackupContext<'a, R: Runtime> {
+    app: &'a AppHandle<R>,
+    control: &'a BackupControl,
+    target: &'a Path,
+    collision_mode: &'a str,
+    /// Ask the frontend to approve every file before it's copied
+    interactive_filter: bool,
+    /// Template for the names picked in `rename` mode, `None` for `_1`, `_2`
+    rename_template: Option<&'a str>,
+    dry_run: bool,
+    preserve_timestamps: bool,
+    preserve_permissions: bool,
+    preserve_xattr: bool,
+    /// Also copy creation times, where the platform can set them
+    preserve_creation_time: bool,
+    incremental: bool,
+    verify: bool,
+    mirror: bool,
+    atomic: bool,
+    /// Delete each source file once it has been copied
+    move_files: bool,
+    /// Clone files copy-on-write where the filesystem supports it
+    prefer_reflink: bool,
+    /// Hardlink files whose content was already copied in this run
+    dedupe: bool,
+    /// Continue copies that were interrupted in a previous run or attempt
+    resume_partial: bool,
+    /// Folder atomic copies are written to instead of next to their
+    /// destination
+    temp_dir: Option<PathBuf>,
+    /// Hashes kept from earlier runs for files that haven't changed
+    hash_cache: Option<HashCache>,
+    /// Root of an earlier backup whose unchanged files are hardlinked
+    link_dest: Option<PathBuf>,
+    /// Copy files only while the target has room, newest first
+    fill_mode: bool,
+    /// Hardlink files reached again through another path, such as a followed
+    /// symlink, to their first copy
+    deduplicate_symlinks: bool,
+    /// Encrypt every copied file with the backup's passphrase
+    encryption: Option<FileEncryptor>,
+    /// How often a transient copy error is retried
+    max_retries: u32,
+    /// Number of errors after which the run is stopped
+    max_errors: Option<u64>,
+    /// Bandwidth cap and per-file timeout
+    chunked: ChunkedCopy,
+    /// Collect manifest entries for every copied file
+    manifest: bool,
+    /// Return the destination path of every copied file
+    collect_file_list: bool,
+    /// How many of the largest copied files to report
+    top_files: usize,
+    /// Minimum time between two progress events
+    progress_interval: Duration,
+    total_count: u64,
+    total_bytes: u64,
+    /// Whether every file is copied into more than one target
+    multiple_targets: bool,
+    stats: Mutex<BackupStats>,
+}
+
+/// Percentage of the backup done, by bytes when there are any and by the
+/// number of files otherwise
+fn progress_percent(done_bytes: u64, total_bytes: u64, done_count: u64, total_count: u64) -> f64 {
+    let fraction = if total_bytes > 0 {
+        done_bytes as f64 / total_bytes as f64
+    } else if total_count > 0 {
+        done_count as f64 / total_count as f64
+    } else {
+        0.0
+    };
+    (fraction * 100.0).clamp(0.0, 100.0)
+}
+
+impl<R: Runtime> BackupContext<'_, R> {
+    fn stats(&self) -> MutexGuard<'_, BackupStats> {
+        self.stats.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Collision mode to apply to a file whose destination exists. In prompt
+    /// mode a `backup-conflict` event asks the frontend, one file at a time,
+    /// and this waits for the answer unless an earlier one applies to all
+    /// conflicts. Cancelling while waiting skips the file.
+    fn collision_mode_for(&self, source: &Path, dest: &Path) -> &str {
+        if self.collision_mode != "prompt" {
+            return self.collision_mode;
+        }
+
+        // Holding the receiver makes other workers wait their turn
+        let decisions = self.control.pending_decisions.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(mode) = self.stats().conflict_answer {
+            return mode;
+        }
+
+        let _ = self.app.emit(
+            "backup-conflict",
+            BackupConflict {
+                source: source.to_string_lossy().to_string(),
+                dest: dest.to_string_lossy().to_string(),
+            },
+        );
+
+        loop {
+            if self.is_cancelled() {
+                return "skip";
+            }
+            match decisions.recv_timeout(CONFLICT_POLL_INTERVAL) {
+                Ok(decision) => {
+                    if decision.all {
+                        self.stats().conflict_answer = Some(decision.mode);
+                    }
+                    return decision.mode;
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return "skip",
+            }
+        }
+    }
+
+    /// In review mode, send a `backup-review` event for a file or symlink and
+    /// wait for the frontend to approve or reject it, unless an earlier
+    /// answer applies to the rest. A rejected file, or one still waiting
+    /// when the run is cancelled, becomes an `Excluded` entry.
+    fn review(&self, entry: PlanEntry) -> PlanEntry {
+        if !self.interactive_filter {
+            return entry;
+        }
+        let (source, relative, size) = match &entry {
+            PlanEntry::File {
+                source,
+                relative,
+                size,
+            } => (source, relative, *size),
+            PlanEntry::Symlink { source, relative } => (source, relative, 0),
+            _ => return entry,
+        };
+
+        let answer = self.stats().review_answer;
+        let approved = answer.unwrap_or_else(|| {
+            let reviews = self.control.pending_reviews.lock().unwrap_or_else(|e| e.into_inner());
+            let _ = self.app.emit(
+                "backup-review",
+                BackupReview {
+                    source: source.to_string_lossy().to_string(),
+                    path: relative.to_string_lossy().to_string(),
+                    size,
+                },
+            );
+
+            loop {
+                if self.is_cancelled() {
+                    return false;
+                }
+                match reviews.recv_timeout(CONFLICT_POLL_INTERVAL) {
+                    Ok(decision) => {
+                        if decision.rest {
+                            self.stats().review_answer = Some(decision.approved);
+                        }
+                        return decision.approved;
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => return false,
+                }
+            }
+        });
+
+        if approved {
+            entry
+        } else {
+            tracing::debug!(file = %relative.display(), "Rejected in review");
+            PlanEntry::Excluded
+        }
+    }
+
+    /// Check the cancellation flag, remembering that it was observed
+    fn is_cancelled(&self) -> bool {
+        if self.control.cancelled.load(Ordering::SeqCst) {
+            self.stats().cancelled = true;
+            return true;
+        }
+        false
+    }
+
+    /// Emit a progress event, unless one was already sent within the last
+    /// `progress_interval`. Throttled updates are flushed by `flush_progress`.
+    fn emit_progress(&self, stats: &mut BackupStats, current_file: &str, verifying: bool) {
+        let now = Instant::now();
+        if let Some(last) = stats.last_progress {
+            if now.duration_since(last) < self.progress_interval {
+                stats.pending_progress = Some(current_file.to_string());
+                return;
+            }
+        }
+
+        stats.last_progress = Some(now);
+        stats.pending_progress = None;
+        self.send_progress(stats, current_file, verifying);
+    }
+
+    /// Count a skipped file and report it like a copied one, so progress keeps
+    /// moving when most files are skipped
+    fn record_skip(&self, current_file: &str, source_index: usize) {
+        tracing::debug!(file = current_file, "Skipped");
+        let mut stats = self.stats();
+        stats.skipped_count += 1;
+        stats.source(source_index).skipped += 1;
+        self.emit_progress(&mut stats, current_file, false);
+    }
+
+    /// Emit the final state if the last progress update was throttled
+    fn flush_progress(&self) {
+        let mut stats = self.stats();
+        if let Some(current_file) = stats.pending_progress.take() {
+            self.send_progress(&mut stats, &current_file, false);
+        }
+    }
+
+    fn send_progress(&self, stats: &mut BackupStats, current_file: &str, verifying: bool) {
+        let copied_bytes = stats.copied_bytes;
+        let rate = stats.rate.sample(Instant::now(), copied_bytes);
+        let eta_seconds = rate.filter(|rate| *rate > 0.0).map(|rate| {
+            (self.total_bytes.saturating_sub(copied_bytes) as f64 / rate).ceil() as u64
+        });
+        let percent = progress_percent(
+            copied_bytes,
+            self.total_bytes,
+            stats.copied_count + stats.skipped_count,
+            self.total_count,
+        );
+        stats.progress_seq += 1;
+
+        let _ = self.app.emit(
+            "backup-progress",
+            BackupProgress {
+                current_file: current_file.to_string(),
+                copied_count: stats.copied_count,
+                skipped_count: stats.skipped_count,
+                total_count: self.total_count,
+                total_bytes: self.total_bytes,
+                copied_bytes,
+                verifying,
+                bytes_per_second: rate.unwrap_or(0.0),
+                eta_seconds,
+                percent,
+                seq: stats.progress_seq,
+            },
+        );
+    }
+
+    /// Remember a destination path so mirror mode doesn't delete it
+    fn keep_path(&self, path: &Path) {
+        if self.mirror {
+            self.stats().expected_paths.insert(path.to_path_buf());
+        }
+    }
+
+    /// Remember a created directory so its timestamp can be restored once
+    /// the copy is done
+    fn track_dir(&self, source: &Path, dest: &Path) {
+        if self.preserve_timestamps && !self.dry_run {
+            self.stats().created_dirs.push((source.to_path_buf(), dest.to_path_buf()));
+        }
+    }
+
+    /// Forward a non-fatal problem to the frontend
+    fn report_warning(&self, message: String, file: &Path) {
+        tracing::warn!(file = %file.display(), "{}", message);
+        let _ = self.app.emit(
+            "backup-warning",
+            BackupWarning {
+                message,
+                file: Some(file.to_string_lossy().to_string()),
+            },
+        );
+    }
+
+    /// Record an error without notifying the frontend
+    fn record_error(&self, message: String, file: Option<&Path>) {
+        self.add_error(BackupError {
+            message,
+            file: file.map(|f| f.to_string_lossy().to_string()),
+        });
+    }
+
+    /// Store an error, cancelling the run once `max_errors` is reached
+    fn add_error(&self, error: BackupError) {
+        let mut stats = self.stats();
+        stats.record_error(error);
+        if !stats.aborted && self.max_errors.is_some_and(|max| stats.error_count >= max) {
+            stats.aborted = true;
+            self.control.cancelled.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Length of an interrupted copy of `source` at `dest` to continue from,
+    /// if resuming is enabled and possible
+    fn resumable_len(&self, source: &Path, dest: &Path) -> Option<u64> {
+        if !self.resume_partial || self.atomic || self.encryption.is_some() {
+            return None;
+        }
+        partial_copy_len(source, dest)
+    }
+
+    /// Count one more file below each of the tracked directories of `dir`
+    fn add_dir_file(&self, source_index: usize, dir: &Path) {
+        let mut stats = self.stats();
+        for dir in relative_ancestors(dir) {
+            if let Some(progress) = stats.open_dirs.get_mut(&(source_index, dir.to_path_buf())) {
+                progress.files += 1;
+            }
+        }
+    }
+
+    /// Queue a copy job, counting it against the directories it's in first so
+    /// a worker can't finish it before it's counted
+    fn queue_job(&self, jobs: &SyncSender<CopyJob>, job: CopyJob) {
+        if let Some(dir) = &job.dir {
+            let mut stats = self.stats();
+            for dir in relative_ancestors(dir) {
+                let key = (job.source_index, dir.to_path_buf());
+                if let Some(progress) = stats.open_dirs.get_mut(&key) {
+                    progress.pending += 1;
+                }
+            }
+        }
+        let _ = jobs.send(job);
+    }
+
+    /// Count a finished job against the directories it's in, reporting each
+    /// one that it was the last job of
+    fn finish_dir_job(&self, source_index: usize, dir: &Path) {
+        let mut stats = self.stats();
+        for dir in relative_ancestors(dir) {
+            let key = (source_index, dir.to_path_buf());
+            let Some(progress) = stats.open_dirs.get_mut(&key) else { continue };
+            progress.pending = progress.pending.saturating_sub(1);
+            if progress.walked && progress.pending == 0 {
+                self.complete_dir(&mut stats, key);
+            }
+        }
+    }
+
+    /// Mark a directory as fully walked, reporting it right away if none of
+    /// its files are still being copied
+    fn finish_dir_walk(&self, source_index: usize, dir: PathBuf) {
+        let mut stats = self.stats();
+        let key = (source_index, dir);
+        let Some(progress) = stats.open_dirs.get_mut(&key) else { return };
+        progress.walked = true;
+        if progress.pending == 0 {
+            self.complete_dir(&mut stats, key);
+        }
+    }
+
+    /// Stop tracking a directory and emit its `backup-dir-complete` event,
+    /// unless the run was cancelled and its files may not have been copied
+    fn complete_dir(&self, stats: &mut BackupStats, key: (usize, PathBuf)) {
+        let Some(progress) = stats.open_dirs.remove(&key) else { return };
+        if self.control.cancelled.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let (source_index, dir) = key;
+        let _ = self.app.emit(
+            "backup-dir-complete",
+            BackupDirComplete {
+                path: dir.to_string_lossy().to_string(),
+                file_count: progress.files,
+                source_index,
+            },
+        );
+    }
+
+    /// Hash of a file's content, from the hash cache if it's in use
+    fn file_hash(&self, path: &Path) -> std::io::Result<blake3::Hash> {
+        match &self.hash_cache {
+            Some(cache) => cache.hash(path),
+            None => hash_file(path),
+        }
+    }
+
+    /// Temporary file an atomic copy to `dest` is written to
+    fn temp_file(&self, dest: &Path) -> PathBuf {
+        match &self.temp_dir {
+            // Files from every folder meet here, so the name stands for the
+            // whole destination path
+            Some(temp_dir) => {
+                let hash = blake3::hash(dest.as_os_str().as_encoded_bytes());
+                temp_dir.join(format!("{}{}", hash.to_hex(), TEMP_SUFFIX))
+            }
+            None => temp_path(dest),
+        }
+    }
+
+    /// Whether fill mode found the target full, after which files are left
+    /// out instead of copied
+    fn is_target_full(&self) -> bool {
+        self.fill_mode && self.stats().target_full
+    }
+
+    /// Note a file that fill mode couldn't fit into the target
+    fn leave_out(&self, display_name: &str) {
+        tracing::debug!(file = display_name, "Left out, the target is full");
+        let mut stats = self.stats();
+        stats.target_full = true;
+        stats.left_out.push(display_name.to_string());
+    }
+
+    /// Count a directory the walk wasn't allowed to read and warn about it
+    fn record_unreadable(&self, dir: &Path) {
+        self.stats().unreadable_count += 1;
+        self.report_warning(
+            format!("Skipped unreadable directory {}", dir.to_string_lossy()),
+            dir,
+        );
+    }
+
+    /// Record an error and forward it to the frontend
+    fn report_error(&self, message: String, file: &Path) {
+        let error = BackupError {
+            message,
+            file: Some(file.to_string_lossy().to_string()),
+        };
+        self.add_error(error.clone());
+        let _ = self.app.emit("backup-error", error);
+    }
+
+    /// Report an error while backing up a file of the source at
+    /// `source_index`
+    fn report_source_error(&self, message: String, file: &Path, source_index: usize) {
+        self.stats().source(source_index).errors += 1;
+        self.report_error(message, file);
+    }
+}
+
+/// Characters Windows doesn't allow in file names
+const ILLEGAL_NAME_CHARS: &[char] = &['<', '>', ':', '"', '|', '?', '*', '\\'];
+
+/// Device names Windows reserves, with any extension
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Unicode normalization form given to destination names
+#[derive(Clone, Copy)]
+enum UnicodeForm {
+    /// Composed, as used on Linux and Windows
+    Nfc,
+    /// Decomposed, as stored by macOS
+    Nfd,
+}
+
+impl UnicodeForm {
+    fn parse(form: &str) -> Option<Self> {
+        match form.to_ascii_lowercase().as_str() {
+            "nfc" => Some(UnicodeForm::Nfc),
+            "nfd" => Some(UnicodeForm::Nfd),
+            _ => None,
+        }
+    }
+}
+
+/// How destination names are rewritten
+#[derive(Clone, Copy)]
+struct NameRules {
+    /// Replace what Windows and cloud services don't allow in names
+    sanitize: bool,
+    lowercase: bool,
+    unicode: Option<UnicodeForm>,
+}
+
+/// A file name rewritten by `rules`. Normalizing comes first. Sanitizing
+/// turns characters Windows forbids and control characters into `_`, drops
+/// trailing dots and spaces and appends `_` to the stem of reserved device
+/// names.
+fn clean_name(name: &OsStr, rules: NameRules) -> Cow<'_, OsStr> {
+    let original = name.to_string_lossy();
+    let mut clean: String = match rules.unicode {
+        Some(UnicodeForm::Nfc) => original.nfc().collect(),
+        Some(UnicodeForm::Nfd) => original.nfd().collect(),
+        None => original.to_string(),
+    };
+    if rules.sanitize {
+        clean = clean
+            .chars()
+            .map(|c| if c.is_control() || ILLEGAL_NAME_CHARS.contains(&c) { '_' } else { c })
+            .collect();
+        clean.truncate(clean.trim_end_matches(['.', ' ']).len());
+        if rules.lowercase {
+            clean = clean.to_lowercase();
+        }
+        if clean.is_empty() {
+            clean.push('_');
+        }
+        let stem_len = clean.find('.').unwrap_or(clean.len());
+        let stem = &clean[..stem_len];
+        if RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(stem)) {
+            clean.insert(stem_len, '_');
+        }
+    }
+
+    if matches!(original, Cow::Borrowed(_)) && clean == original {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(clean.into())
+    }
+}
+
+/// `path` with every component passed through `clean_name`
+fn clean_path(path: &Path, names: Option<NameRules>) -> Cow<'_, Path> {
+    let Some(rules) = names else { return Cow::Borrowed(path) };
+    let mut changed = false;
+    let sanitized: PathBuf = path
+        .iter()
+        .map(|name| {
+            let clean = clean_name(name, rules);
+            changed |= matches!(clean, Cow::Owned(_));
+            clean
+        })
+        .collect();
+    if changed {
+        Cow::Owned(sanitized)
+    } else {
+        Cow::Borrowed(path)
+    }
+}
+
+/// `dir` and each of its parents, innermost first, without the empty path of
+/// the source itself
+fn relative_ancestors(dir: &Path) -> impl Iterator<Item = &Path> {
+    dir.ancestors().take_while(|dir| !dir.as_os_str().is_empty())
+}
+
+/// Appended to the name of every temporary file
+const TEMP_SUFFIX: &str = ".m4ssc0py.tmp";
+
+/// Path of the temporary file used while atomically copying to `dest`
+fn temp_path(dest: &Path) -> PathBuf {
+    let mut name = dest.file_name().unwrap_or_default().to_os_string();
+    name.push(TEMP_SUFFIX);
+    dest.with_file_name(name)
+}
+
+/// Whether two existing paths are on the same volume, so a file can be
+/// renamed from one to the other
+#[cfg(unix)]
+fn same_volume(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_volume(a: &Path, b: &Path) -> bool {
+    // The drive or network share the canonical path starts with
+    let volume = |path: &Path| {
+        let path = path.canonicalize().ok()?;
+        path.components().next().map(|c| c.as_os_str().to_ascii_lowercase())
+    };
+    matches!((volume(a), volume(b)), (Some(a), Some(b)) if a == b)
+}
+
+/// Get `temp_dir` ready to stage atomic copies for `targets`: create it,
+/// check that files can be renamed from it into each target, and remove the
+/// temporary files an interrupted run left behind
+fn prepare_temp_dir(temp_dir: &Path, targets: &[PathBuf]) -> Result<(), String> {
+    create_dir(temp_dir, false)
+        .map_err(|e| format!("Failed to create temporary folder: {}", e))?;
+    if let Some(target) = targets.iter().find(|target| !same_volume(temp_dir, target)) {
+        return Err(format!(
+            "Temporary folder {} isn't on the same volume as {}, so files are staged \
+             next to their destination instead to keep copies atomic",
+            temp_dir.to_string_lossy(),
+            target.to_string_lossy()
+        ));
+    }
+
+    let entries = fs::read_dir(temp_dir).map_err(|e| e.to_string())?;
+    for entry in entries.flatten() {
+        if entry.file_name().to_string_lossy().ends_with(TEMP_SUFFIX) {
+            remove_stale_temp_file(&entry.path());
+        }
+    }
+    Ok(())
+}
+
+/// Remove the temporary files an interrupted run left next to their
+/// destinations anywhere below `target`
+fn remove_stale_temp_files(target: &Path) {
+    let walker = WalkBuilder::new(target).standard_filters(false).build();
+    for dir_entry in walker.flatten() {
+        let is_file = dir_entry.file_type().is_some_and(|t| t.is_file());
+        if is_file && dir_entry.file_name().to_string_lossy().ends_with(TEMP_SUFFIX) {
+            remove_stale_temp_file(dir_entry.path());
+        }
+    }
+}
+
+fn remove_stale_temp_file(path: &Path) {
+    match fs::remove_file(path) {
+        Ok(()) => tracing::info!(file = %path.display(), "Removed stale temp file"),
+        Err(e) => tracing::warn!(file = %path.display(), "{}", e),
+    }
+}
+
+/// Copy a file to its destination. In atomic mode the data is written to a
+/// temporary file next to the destination or in `temp_dir`, which is only
+/// renamed into place once the copy (and verification) fully succeeded, so
+/// an interrupted backup never leaves a truncated file behind.
+fn copy_to_destination<R: Runtime>(
+    ctx: &BackupContext<R>,
+    source: &Path,
+    dest: &Path,
+    display_name: &str,
+) -> std::io::Result<CopiedFile> {
+    if !ctx.atomic || ctx.dry_run {
+        return copy_verified(ctx, source, dest, display_name);
+    }
+
+    let temp = ctx.temp_file(dest);
+    let result = copy_verified(ctx, source, &temp, display_name).and_then(|copied| {
+        fs::rename(long_path(&temp), long_path(dest)).map(|_| copied).map_err(|e| {
+            std::io::Error::new(e.kind(), format!("Failed to move copied file into place: {}", e))
+        })
+    });
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Bytes at the end of a partial copy compared with the source before the
+/// copy is continued
+const RESUME_CHECK_SIZE: u64 = 1024 * 1024;
+
+/// Length of a copy of `source` at `dest` that was cut short: `dest` is a
+/// shorter file whose last `RESUME_CHECK_SIZE` bytes match the source at the
+/// same offset. `None` if there's nothing to continue from.
+fn partial_copy_len(source: &Path, dest: &Path) -> Option<u64> {
+    let source_len = fs::metadata(long_path(source)).ok()?.len();
+    let dest_metadata = fs::symlink_metadata(long_path(dest)).ok()?;
+    let len = dest_metadata.len();
+    // Continuing a hardlinked file would append to its other links too
+    let linked = has_other_links(&dest_metadata);
+    if !dest_metadata.is_file() || linked || len == 0 || len >= source_len {
+        return None;
+    }
+
+    let check_len = len.min(RESUME_CHECK_SIZE);
+    let read_tail = |path: &Path| -> std::io::Result<Vec<u8>> {
+        let mut file = fs::File::open(long_path(path))?;
+        file.seek(SeekFrom::Start(len - check_len))?;
+        let mut tail = vec![0; check_len as usize];
+        file.read_exact(&mut tail)?;
+        Ok(tail)
+    };
+    (read_tail(source).ok()? == read_tail(dest).ok()?).then_some(len)
+}
+
+/// Delay before the first retry of a transient error, doubled on every retry
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(200);
+
+/// Whether an I/O error is worth retrying, as opposed to a permanent failure
+fn is_transient(error: &std::io::Error) -> bool {
+    matches!(
+        error.kind(),
+        std::io::ErrorKind::Interrupted | std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock
+    )
+}
+
+/// Copy a file, retrying transient errors up to `max_retries` times with
+/// exponential backoff. The source is hashed while copying when the result
+/// is verified or goes into the manifest.
+fn copy_with_retry<R: Runtime>(
+    ctx: &BackupContext<R>,
+    source: &Path,
+    dest: &Path,
+) -> std::io::Result<(u64, bool, Option<blake3::Hash>)> {
+    let mut attempt = 0;
+    let mut delay = RETRY_BASE_DELAY;
+    let hash = ctx.verify || ctx.manifest;
+
+    loop {
+        // Checked on every attempt, so a retry continues where the last one
+        // stopped
+        let resume_from = ctx.resumable_len(source, dest).filter(|_| !ctx.dry_run);
+        let result = match (&ctx.encryption, resume_from) {
+            (Some(encryptor), _) if !ctx.dry_run => {
+                replace_file(dest, |dest| encryptor.encrypt_file(source, dest))
+                    .map(|bytes| (bytes, false, None))
+            }
+            (_, Some(offset)) => ctx
+                .chunked
+                .copy(&long_path(source), &long_path(dest), hash, offset)
+                .map(|(bytes, hash)| (bytes, false, hash)),
+            _ => copy_file(source, dest, ctx.dry_run, ctx.prefer_reflink, hash, &ctx.chunked),
+        };
+        // Tests fail chosen files once their data has been written
+        #[cfg(test)]
+        let result = match ctx.control.injected_fault(source, attempt) {
+            Some(e) => Err(e),
+            None => result,
+        };
+
+        match result {
+            Err(e) if is_transient(&e) && attempt < ctx.max_retries && !ctx.is_cancelled() => {
+                attempt += 1;
+                let _ = ctx.app.emit(
+                    "backup-retry",
+                    BackupRetry {
+                        file: source.to_string_lossy().to_string(),
+                        attempt,
+                        message: e.to_string(),
+                    },
+                );
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Copy a file, checking the destination against the source's checksum when
+/// verification is enabled. A mismatching copy is deleted and retried once.
+fn copy_verified<R: Runtime>(
+    ctx: &BackupContext<R>,
+    source: &Path,
+    dest: &Path,
+    display_name: &str,
+) -> std::io::Result<CopiedFile> {
+    let mut retried = false;
+    let verify_error = |e: std::io::Error| {
+        std::io::Error::new(e.kind(), format!("Failed to verify: {}", e))
+    };
+
+    loop {
+        let (bytes, reflinked, copied_hash) = copy_with_retry(ctx, source, dest)?;
+
+        // An encrypted copy can't be compared with its source
+        if !ctx.verify || ctx.dry_run || ctx.encryption.is_some() {
+            return Ok(CopiedFile {
+                bytes,
+                reflinked,
+                deduped: false,
+                checksum: copied_hash,
+            });
+        }
+
+        ctx.emit_progress(&mut ctx.stats(), display_name, true);
+
+        // The source was hashed while copying unless it was reflinked, so
+        // usually only the destination is read back
+        let source_hash = match copied_hash {
+            Some(hash) => hash,
+            None => ctx.file_hash(source).map_err(verify_error)?,
+        };
+        let dest_hash = hash_file(dest).map_err(verify_error)?;
+        if source_hash == dest_hash {
+            return Ok(CopiedFile {
+                bytes,
+                reflinked,
+                deduped: false,
+                checksum: Some(source_hash),
+            });
+        }
+
+        // The second copy replaces the first, so a claimed name stays taken
+        if retried {
+            let _ = fs::remove_file(dest);
+            return Err(std::io::Error::other("Checksum mismatch after copy"));
+        }
+        retried = true;
+    }
+}
+
+/// Hardlink `dest` to `original`, an earlier copy of identical content.
+/// Returns `None` if linking isn't possible, e.g. across devices, so the
+/// caller can fall back to a regular copy.
+fn link_duplicate<R: Runtime>(
+    ctx: &BackupContext<R>,
+    source: &Path,
+    original: &Path,
+    dest: &Path,
+) -> Option<CopiedFile> {
+    if original == dest {
+        return None;
+    }
+    let bytes = fs::metadata(source).ok()?.len();
+
+    if !ctx.dry_run {
+        let original = long_path(original);
+        replace_file(&long_path(dest), |dest| fs::hard_link(&original, dest)).ok()?;
+    }
+
+    Some(CopiedFile {
+        bytes,
+        reflinked: false,
+        deduped: true,
+        checksum: None,
+    })
+}
+
+/// Resolve collisions for a queued file and copy it
+fn copy_job<R: Runtime>(ctx: &BackupContext<R>, job: CopyJob) {
+    if ctx.is_cancelled() {
+        return;
+    }
+    if ctx.is_target_full() {
+        ctx.leave_out(&job.display_name);
+        return;
+    }
+
+    let mut dest_path = job.dest;
+    // Whether `dest_path` is an empty placeholder claimed for a renamed copy
+    let mut placeholder = false;
+    // Whether the copy replaces an older version of the file
+    let mut replaced = false;
+
+    // Skip files that haven't changed since the last backup
+    if ctx.incremental && is_unchanged(&job.source, &dest_path) {
+        ctx.record_skip(&job.display_name, job.source_index);
+        return;
+    }
+
+    // Handle collision, unless the destination is an interrupted copy that
+    // will be continued
+    if dest_path.exists() && ctx.resumable_len(&job.source, &dest_path).is_none() {
+        match ctx.collision_mode_for(&job.source, &dest_path) {
+            "skip" => {
+                ctx.record_skip(&job.display_name, job.source_index);
+                return;
+            }
+            "rename" => {
+                dest_path = find_available_name(&dest_path, ctx.rename_template, ctx.dry_run);
+                placeholder = !ctx.dry_run;
+            }
+            // Falls through to overwrite when the source is newer or either
+            // timestamp is unavailable
+            "newer" if is_newer(&job.source, &dest_path) == Some(false) => {
+                ctx.record_skip(&job.display_name, job.source_index);
+                return;
+            }
+            "hash" if has_same_content(&job.source, &dest_path, &|path| ctx.file_hash(path)) => {
+                ctx.record_skip(&job.display_name, job.source_index);
+                return;
+            }
+            // Overwrite. A readonly file can only be replaced once it's made
+            // writable; its permissions are set again after the copy.
+            _ => {
+                if !ctx.dry_run {
+                    let _ = make_writable(&dest_path);
+                }
+                replaced = true;
+            }
+        }
+    }
+
+    ctx.keep_path(&dest_path);
+
+    // Look for an unchanged copy in the previous backup
+    let previous = match &ctx.link_dest {
+        Some(link_dest) if !job.symlink => dest_path
+            .strip_prefix(ctx.target)
+            .ok()
+            .map(|relative| link_dest.join(relative))
+            .filter(|previous| is_unchanged(&job.source, previous)),
+        _ => None,
+    };
+    let mut linked_previous = false;
+
+    // Look for identical content copied earlier in this run
+    let content_hash = if ctx.dedupe && !job.symlink {
+        ctx.file_hash(&job.source).ok()
+    } else {
+        None
+    };
+    // Or for the same real file copied through another path
+    let real_path = if ctx.deduplicate_symlinks && !job.symlink {
+        job.source.canonicalize().ok()
+    } else {
+        None
+    };
+    let duplicate_of = {
+        let stats = ctx.stats();
+        content_hash
+            .and_then(|hash| stats.dedupe_index.get(&hash))
+            .or_else(|| real_path.as_ref().and_then(|path| stats.real_path_index.get(path)))
+            .cloned()
+    };
+
+    let result = if job.symlink {
+        copy_symlink(&job.source, &dest_path, ctx.dry_run)
+            .map_err(std::io::Error::other)
+            .map(|bytes| CopiedFile {
+                bytes,
+                reflinked: false,
+                deduped: false,
+                checksum: None,
+            })
+    } else if let Some(linked) =
+        previous.and_then(|previous| link_duplicate(ctx, &job.source, &previous, &dest_path))
+    {
+        linked_previous = true;
+        Ok(linked)
+    } else if let Some(linked) =
+        duplicate_of.and_then(|original| link_duplicate(ctx, &job.source, &original, &dest_path))
+    {
+        Ok(linked)
+    } else {
+        copy_to_destination(ctx, &job.source, &dest_path, &job.display_name)
+    };
+
+    match result {
+        // A hardlink shares the metadata of the file it points to
+        Ok(copied) if copied.deduped => {
+            let mut stats = ctx.stats();
+            stats.copied_count += 1;
+            stats.copied_bytes += copied.bytes;
+            stats.updated_count += replaced as u64;
+            if linked_previous {
+                stats.linked_count += 1;
+            } else {
+                stats.deduped_count += 1;
+            }
+            stats.count_source(job.source_index, copied.bytes);
+            ctx.emit_progress(&mut stats, &job.display_name, false);
+            drop(stats);
+
+            if ctx.move_files {
+                remove_source(ctx, &job.source);
+            }
+        }
+        Ok(copied) => {
+            if ctx.preserve_xattr && !ctx.dry_run && !job.symlink {
+                for failure in xattrs::copy_extended_attributes(&job.source, &dest_path) {
+                    ctx.report_warning(failure, &dest_path);
+                }
+            }
+
+            // Set first, as macOS moves the creation time back along with an
+            // earlier modification time but not forward again
+            if ctx.preserve_creation_time && !ctx.dry_run && !job.symlink {
+                if let Err(e) = copy_creation_time(&job.source, &dest_path) {
+                    let message = format!("Failed to preserve creation time: {}", e);
+                    ctx.report_warning(message, &dest_path);
+                }
+            }
+
+            if ctx.preserve_timestamps && !ctx.dry_run && !job.symlink {
+                if let Err(e) = copy_mtime(&job.source, &dest_path) {
+                    ctx.report_warning(format!("Failed to preserve timestamp: {}", e), &dest_path);
+                }
+            }
+
+            // Applied after the timestamp, which a readonly file may refuse
+            if ctx.preserve_permissions && !ctx.dry_run && !job.symlink {
+                if let Err(e) = copy_permissions(&job.source, &dest_path) {
+                    ctx.report_warning(format!("Failed to preserve permissions: {}", e), &dest_path);
+                }
+            }
+
+            let mut stats = ctx.stats();
+            stats.copied_count += 1;
+            stats.copied_bytes += copied.bytes;
+            stats.updated_count += replaced as u64;
+            if copied.reflinked {
+                stats.reflinked_count += 1;
+            }
+            stats.count_source(job.source_index, copied.bytes);
+            tracing::debug!(file = %dest_path.display(), bytes = copied.bytes, "Copied");
+            // Counted once, not again for every extra target
+            if dest_path.starts_with(ctx.target) {
+                stats.track_largest(&job.source, copied.bytes, ctx.top_files);
+            }
+            if let Some(hash) = content_hash {
+                stats.dedupe_index.entry(hash).or_insert_with(|| dest_path.clone());
+            }
+            if let Some(real_path) = real_path {
+                stats.real_path_index.entry(real_path).or_insert_with(|| dest_path.clone());
+            }
+
+            if ctx.collect_file_list {
+                stats.copied_files.push(dest_path.to_string_lossy().to_string());
+            }
+
+            // The manifest describes the primary target only
+            if ctx.manifest && dest_path.starts_with(ctx.target) {
+                let modified = fs::symlink_metadata(&job.source)
+                    .and_then(|m| m.modified())
+                    .ok()
+                    .and_then(manifest::unix_seconds);
+                let relative = dest_path.strip_prefix(ctx.target).unwrap_or(&dest_path);
+                stats.manifest.push(ManifestEntry {
+                    path: relative.to_string_lossy().to_string(),
+                    source: job.source.to_string_lossy().to_string(),
+                    size: copied.bytes,
+                    modified,
+                    checksum: copied
+                        .checksum
+                        .or(content_hash)
+                        .map(|hash| hash.to_hex().to_string()),
+                });
+            }
+
+            ctx.emit_progress(&mut stats, &job.display_name, false);
+            drop(stats);
+
+            let checksum = copied.checksum.or(content_hash);
+            if let (Some(cache), Some(hash)) = (&ctx.hash_cache, checksum) {
+                if !ctx.dry_run && !job.symlink {
+                    cache.remember(&job.source, hash);
+                    // An encrypted copy has other content than its source
+                    if ctx.encryption.is_none() {
+                        cache.remember(&dest_path, hash);
+                    }
+                }
+            }
+
+            if ctx.move_files {
+                remove_source(ctx, &job.source);
+            }
+        }
+        // Fill mode stops copying once the target runs out of space. A file
+        // that was new to the target is removed to free what it took, while
+        // a replaced one is still intact.
+        Err(e) if ctx.fill_mode && e.kind() == std::io::ErrorKind::StorageFull => {
+            if placeholder || !replaced && !ctx.atomic {
+                let _ = fs::remove_file(&dest_path);
+            }
+            ctx.leave_out(&job.display_name);
+        }
+        Err(e) => {
+            if placeholder {
+                let _ = fs::remove_file(&dest_path);
+            }
+
+            let e = e.to_string();
+            let message = if ctx.multiple_targets {
+                format!("{} (copying to {})", e, dest_path.to_string_lossy())
+            } else {
+                e
+            };
+            ctx.report_source_error(message, &job.source, job.source_index);
+        }
+    }
+}
+
+/// Delete a source file that has been copied in move mode
+fn remove_source<R: Runtime>(ctx: &BackupContext<R>, source: &Path) {
+    let result = if ctx.dry_run {
+        Ok(())
+    } else {
+        fs::remove_file(source)
+    };
+
+    match result {
+        Ok(()) => ctx.stats().moved_count += 1,
+        Err(e) => ctx.report_error(format!("Failed to remove source: {}", e), source),
+    }
+}
+
+/// Remove the source directories left empty by move mode, deepest first.
+/// Directories that still contain anything, such as filtered or failed
+/// files, are kept, and so are the selected source folders themselves.
+fn remove_empty_source_dirs<R: Runtime>(ctx: &BackupContext<R>) {
+    let mut dirs = std::mem::take(&mut ctx.stats().source_dirs);
+    dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+
+    for dir in dirs {
+        let is_empty = fs::read_dir(&dir).is_ok_and(|mut entries| entries.next().is_none());
+        if is_empty {
+            if let Err(e) = fs::remove_dir(&dir) {
+                ctx.report_warning(format!("Failed to remove source dir: {}", e), &dir);
+            }
+        }
+    }
+}
+
+/// Give every created directory the modified time of its source. Runs once
+/// all files are in place, deepest first, since adding entries to a directory
+/// resets its timestamp.
+fn restore_dir_mtimes<R: Runtime>(ctx: &BackupContext<R>) {
+    let mut dirs = std::mem::take(&mut ctx.stats().created_dirs);
+    dirs.sort_by_key(|(_, dest)| std::cmp::Reverse(dest.components().count()));
+
+    for (source, dest) in dirs {
+        if let Err(e) = copy_mtime(&source, &dest) {
+            ctx.report_warning(format!("Failed to preserve timestamp: {}", e), &dest);
+        }
+    }
+}
+
+/// Folder a source is placed in, made of the last `depth` folder names of
+/// its path: those of a directory itself, or for a single file those of the
+/// directory containing it, falling back to the file's stem when the path
+/// has none. Empty for a depth of 0, and shorter when the path is.
+fn source_folder(source: &Path, depth: usize) -> PathBuf {
+    if depth == 0 {
+        return PathBuf::new();
+    }
+
+    let dir = if source.is_dir() { Some(source) } else { source.parent() };
+    let names: Vec<&OsStr> = dir
+        .into_iter()
+        .flat_map(Path::components)
+        .filter_map(|component| match component {
+            std::path::Component::Normal(name) => Some(name),
+            _ => None,
+        })
+        .collect();
+    if names.is_empty() && !source.is_dir() {
+        return source.file_stem().map(PathBuf::from).unwrap_or_default();
+    }
+    names[names.len().saturating_sub(depth)..].iter().collect()
+}
+
+/// Resolve the directory a source directory's contents, or a single source
+/// file, are copied into
+fn effective_target(source: &Path, target: &Path, source_depth: usize) -> PathBuf {
+    let folder = source_folder(source, source_depth);
+    if folder.as_os_str().is_empty() {
+        return target.to_path_buf();
+    }
+    target.join(folder)
+}
+
+/// Order in which collected entries are copied
+#[derive(Clone, Copy, PartialEq)]
+enum SortOrder {
+    /// By relative path
+    Name,
+    /// Smallest file first
+    Size,
+    /// Oldest file first
+    Modified,
+    /// Newest file first, for fill mode
+    NewestFirst,
+}
+
+impl SortOrder {
+    /// Parse a `sort_order` setting, `None` for the walk's own order
+    fn parse(order: &str) -> Result<Option<Self>, String> {
+        match order {
+            "none" => Ok(None),
+            "name" => Ok(Some(SortOrder::Name)),
+            "size" => Ok(Some(SortOrder::Size)),
+            "mtime" => Ok(Some(SortOrder::Modified)),
+            _ => Err(format!("Unknown sort order: {}", order)),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            SortOrder::Name => "name",
+            SortOrder::Size => "size",
+            SortOrder::Modified => "mtime",
+            SortOrder::NewestFirst => "newest first",
+        }
+    }
+}
+
+/// Sort the entries of each source. By name every directory still comes
+/// right before its contents, as in the walk. The other orders move the
+/// files behind every other entry, so directories are still created before
+/// files are copied into them.
+fn sort_plan(plan: &mut [PlanEntry], order: SortOrder) {
+    for entries in plan.split_mut(|entry| matches!(entry, PlanEntry::Root { .. })) {
+        if order == SortOrder::Name {
+            entries.sort_by(|a, b| a.relative().cmp(&b.relative()));
+            continue;
+        }
+
+        entries.sort_by_cached_key(|entry| match entry {
+            PlanEntry::File { source, .. } | PlanEntry::Symlink { source, .. } => {
+                let metadata = fs::symlink_metadata(source).ok();
+                // Nanoseconds since the Unix epoch
+                let modified = || {
+                    let time = metadata.as_ref()?.modified().ok()?;
+                    Some(match time.duration_since(UNIX_EPOCH) {
+                        Ok(since) => since.as_nanos() as i128,
+                        Err(e) => -(e.duration().as_nanos() as i128),
+                    })
+                };
+                // Files whose metadata can't be read go last
+                let key = match order {
+                    SortOrder::Size => metadata.as_ref().map(|m| m.len() as i128),
+                    SortOrder::NewestFirst => modified().map(|nanos| -nanos),
+                    _ => modified(),
+                };
+                (true, key.unwrap_or(i128::MAX))
+            }
+            _ => (false, 0),
+        });
+    }
+}
+
+/// Free space on the file system `path` is on, looked up on its closest
+/// existing ancestor so a target that isn't created yet, as in a dry run,
+/// works too
+fn available_space(path: &Path) -> Option<u64> {
+    let existing = path.ancestors().find(|ancestor| ancestor.exists())?;
+    fs4::available_space(existing).ok()
+}
+
+/// Files already in the targets that none of the sources would produce, such
+/// as those of an unrelated folder picked as the target by mistake. The report
+/// and hash cache of an earlier backup don't count as foreign. `dest_name`
+/// gives the path a file or link entry is copied to, relative to its source's
+/// folder in the target, and `None` for other entries.
+fn find_foreign_files(
+    sources: &[Source],
+    targets: &[PathBuf],
+    options: &WalkOptions,
+    source_depth: usize,
+    flatten: bool,
+    dest_name: &dyn Fn(&PlanEntry) -> Option<PathBuf>,
+) -> Vec<PathBuf> {
+    // Destinations relative to the target, the same in each of them
+    let mut expected: HashSet<PathBuf> = HashSet::new();
+    for source in sources {
+        let path = Path::new(source.path);
+        let root = if flatten || (!path.is_dir() && source_depth == 0) {
+            PathBuf::new()
+        } else {
+            effective_target(path, Path::new(""), source_depth)
+        };
+        let _ = walk_source(path, source.filter, options, &mut |entry| {
+            if let Some(relative) = dest_name(&entry) {
+                let dest = match relative.file_name() {
+                    Some(name) if flatten => PathBuf::from(name),
+                    _ => root.join(&relative),
+                };
+                expected.insert(dest);
+            }
+            ControlFlow::Continue(())
+        });
+    }
+
+    let mut foreign = Vec::new();
+    for target in targets {
+        let walker = WalkBuilder::new(target).standard_filters(false).build();
+        for dir_entry in walker.flatten() {
+            if dir_entry.file_type().is_none_or(|t| t.is_dir()) {
+                continue;
+            }
+            let Ok(relative) = dir_entry.path().strip_prefix(target) else { continue };
+            let own_file = [report::REPORT_FILENAME, HASH_CACHE_FILENAME]
+                .iter()
+                .any(|name| relative == Path::new(name));
+            if !expected.contains(relative) && !own_file {
+                foreign.push(dir_entry.path().to_path_buf());
+            }
+        }
+    }
+    foreign
+}
+
+/// Delete everything under `root` that this backup didn't produce. The root
+/// itself and anything outside it are never touched. In dry-run mode the
+/// deletions are only reported.
+fn mirror_target<R: Runtime>(ctx: &BackupContext<R>, root: &Path) {
+    let expected = std::mem::take(&mut ctx.stats().expected_paths);
+    let mut removed_dir: Option<PathBuf> = None;
+
+    let walker = WalkBuilder::new(root).standard_filters(false).build();
+
+    for dir_entry in walker.flatten() {
+        let path = dir_entry.path();
+
+        if path == root || !path.starts_with(root) || expected.contains(path) {
+            continue;
+        }
+
+        // Children of a directory that was just removed are already gone
+        if removed_dir.as_deref().is_some_and(|dir| path.starts_with(dir)) {
+            continue;
+        }
+
+        let is_dir = dir_entry.file_type().is_some_and(|t| t.is_dir());
+        let result = if ctx.dry_run {
+            Ok(())
+        } else if is_dir {
+            fs::remove_dir_all(path)
+        } else {
+            fs::remove_file(path)
+        };
+
+        match result {
+            Ok(()) => {
+                if is_dir {
+                    removed_dir = Some(path.to_path_buf());
+                }
+                ctx.stats().deleted_count += 1;
+                let _ = ctx.app.emit(
+                    "backup-deleted",
+                    BackupDeleted {
+                        path: path.to_string_lossy().to_string(),
+                    },
+                );
+            }
+            Err(e) => {
+                ctx.report_error(format!("Failed to delete: {}", e), path);
+            }
+        }
+    }
+
+    ctx.stats().expected_paths = expected;
+}
+
+/// How symbolic links inside a source tree are handled
+#[derive(Clone, Copy, PartialEq)]
+enum SymlinkMode {
+    /// Copy whatever the link points to
+    Follow,
+    /// Recreate the link at the destination (Unix only, skipped elsewhere)
+    Copy,
+    /// Leave links out of the backup
+    Skip,
+}
+
+impl SymlinkMode {
+    fn parse(mode: &str) -> Self {
+        match mode {
+            "copy" => SymlinkMode::Copy,
+            "skip" => SymlinkMode::Skip,
+            _ => SymlinkMode::Follow,
+        }
+    }
+}
+
+/// Traversal settings shared by every walk over a source tree
+struct WalkOptions {
+    /// Visit dotfiles and, on Windows, files with the hidden attribute
+    include_hidden: bool,
+    /// Honor `.gitignore` files and `.git/info/exclude`, including those of
+    /// a repository the source is only a subfolder of
+    respect_gitignore: bool,
+    /// Honor `BACKUP_IGNORE_FILENAME` files found in the source tree, and
+    /// with `respect_gitignore` also those in the folders above it
+    respect_backupignore: bool,
+    symlink_mode: SymlinkMode,
+    /// How many directory levels below the source to descend; 0 visits only
+    /// the source's direct children
+    max_depth: Option<usize>,
+    /// Canonical paths that are never entered, such as a target inside a source
+    skip_paths: Vec<PathBuf>,
+    /// Read named pipes, sockets and devices like regular files instead of
+    /// skipping them
+    copy_special_files: bool,
+}
+
+/// Per-directory ignore file using `.gitignore` syntax. Its patterns apply on
+/// top of the blacklist.
+const BACKUP_IGNORE_FILENAME: &str = ".m4ssc0pyignore";
+
+impl WalkOptions {
+    fn builder(&self, source: &Path) -> WalkBuilder {
+        let mut builder = WalkBuilder::new(source);
+        builder
+            .hidden(!self.include_hidden)
+            .git_ignore(self.respect_gitignore)
+            .git_global(false)
+            .git_exclude(self.respect_gitignore)
+            // Ignore files above the source apply as well, up to the root of
+            // the repository it's in, as git would apply them. Without that,
+            // only those inside the source are read.
+            .parents(self.respect_gitignore)
+            // The walker counts the source itself as depth 0
+            .max_depth(self.max_depth.map(|depth| depth + 1));
+
+        if self.respect_backupignore {
+            builder.add_custom_ignore_filename(BACKUP_IGNORE_FILENAME);
+        }
+
+        // Enter each real directory only once, so links pointing back up the
+        // tree or at an already visited directory can't loop forever or
+        // duplicate content
+        let visited = (self.symlink_mode == SymlinkMode::Follow).then(|| {
+            let visited: Arc<Mutex<HashSet<PathBuf>>> = Arc::default();
+            if let Ok(root) = source.canonicalize() {
+                visited.lock().unwrap_or_else(|e| e.into_inner()).insert(root);
+            }
+            builder.follow_links(true);
+            visited
+        });
+
+        let skip_paths = self.skip_paths.clone();
+        if visited.is_none() && skip_paths.is_empty() {
+            return builder;
+        }
+
+        builder.filter_entry(move |entry| {
+            // Only entries named like a skipped path are worth canonicalizing
+            let maybe_skipped = skip_paths
+                .iter()
+                .any(|path| path.file_name() == Some(entry.file_name()));
+            if maybe_skipped
+                && entry
+                    .path()
+                    .canonicalize()
+                    .is_ok_and(|real| skip_paths.contains(&real))
+            {
+                return false;
+            }
+
+            let Some(visited) = &visited else { return true };
+            if !entry.file_type().is_some_and(|t| t.is_dir()) {
+                return true;
+            }
+            match entry.path().canonicalize() {
+                Ok(real) => visited.lock().unwrap_or_else(|e| e.into_inner()).insert(real),
+                Err(_) => true,
+            }
+        });
+
+        builder
+    }
+}
+
+/// A step of the backup plan, produced in traversal order
+enum PlanEntry {
+    /// Start of a new source path; later entries are relative to it
+    Root { source: PathBuf },
+    /// A directory to recreate at the destination
+    Dir { source: PathBuf, relative: PathBuf },
+    /// A file to copy
+    File {
+        source: PathBuf,
+        relative: PathBuf,
+        size: u64,
+    },
+    /// A symbolic link to recreate as-is
+    Symlink { source: PathBuf, relative: PathBuf },
+    /// A named pipe, socket or device, left out since copying it would block
+    /// or fail
+    Special { source: PathBuf, kind: &'static str },
+    /// A directory that couldn't be read for lack of permission, so nothing
+    /// below it is backed up
+    Unreadable { path: PathBuf },
+    /// A file left out by the filters
+    Excluded,
+    /// A problem encountered while walking
+    Error(String),
+}
+
+impl PlanEntry {
+    /// Path relative to the source, for entries that have one
+    fn relative(&self) -> Option<&Path> {
+        match self {
+            PlanEntry::Dir { relative, .. }
+            | PlanEntry::File { relative, .. }
+            | PlanEntry::Symlink { relative, .. } => Some(relative),
+            _ => None,
+        }
+    }
+
+    /// Size counted toward the totals, or `None` if the entry isn't a file
+    fn file_size(&self) -> Option<u64> {
+        match self {
+            PlanEntry::File { size, .. } => Some(*size),
+            PlanEntry::Symlink { .. } => Some(0),
+            _ => None,
+        }
+    }
+}
+
+/// A source path together with the filter its walk applies
+struct Source<'a> {
+    path: &'a str,
+    filter: &'a FileFilter,
+}
+
+/// Build the plan entry for a file, applying the size and modification time
+/// filters
+fn sized_file_entry(
+    filter: &FileFilter,
+    source: &Path,
+    relative: &Path,
+    metadata: Option<fs::Metadata>,
+) -> PlanEntry {
+    let size = metadata.as_ref().map(|m| m.len()).unwrap_or(0);
+    let modified = metadata.and_then(|m| m.modified().ok());
+    if !filter.accepts_size(size) || !filter.accepts_modified(modified) {
+        return PlanEntry::Excluded;
+    }
+    PlanEntry::File {
+        source: source.to_path_buf(),
+        relative: relative.to_path_buf(),
+        size,
+    }
+}
+
+/// Name the kind of a file that is neither a regular file, a directory nor a
+/// link
+#[cfg(unix)]
+fn special_file_kind(file_type: &fs::FileType) -> &'static str {
+    use std::os::unix::fs::FileTypeExt;
+    if file_type.is_fifo() {
+        "named pipe"
+    } else if file_type.is_socket() {
+        "socket"
+    } else if file_type.is_block_device() {
+        "block device"
+    } else if file_type.is_char_device() {
+        "character device"
+    } else {
+        "special file"
+    }
+}
+
+#[cfg(not(unix))]
+fn special_file_kind(_file_type: &fs::FileType) -> &'static str {
+    "special file"
+}
+
+/// The path a walker error is about, if it was refused for lack of
+/// permission
+fn unreadable_path(error: &ignore::Error) -> Option<&Path> {
+    let denied = error
+        .io_error()
+        .is_some_and(|e| e.kind() == std::io::ErrorKind::PermissionDenied);
+    match error {
+        ignore::Error::WithPath { path, .. } if denied => Some(path),
+        ignore::Error::WithDepth { err, .. } => unreadable_path(err),
+        _ => None,
+    }
+}
+
+/// Walk a single source path and report what should be backed up, after
+/// filtering. Both the counting and the copying passes are built on this so
+/// they always agree. Returning `ControlFlow::Break` from `visit` stops the walk.
+fn walk_source(
+    source: &Path,
+    filter: &FileFilter,
+    options: &WalkOptions,
+    visit: &mut dyn FnMut(PlanEntry) -> ControlFlow<()>,
+) -> ControlFlow<()> {
+    visit(PlanEntry::Root {
+        source: source.to_path_buf(),
+    })?;
+
+    if source.is_file() {
+        // Handle single file
+        if let Some(file_name) = source.file_name() {
+            // Check blacklist and whitelist
+            if filter.accepts_file(Path::new(file_name)) && !filter.is_excluded_absolute(source) {
+                visit(sized_file_entry(
+                    filter,
+                    source,
+                    Path::new(file_name),
+                    source.metadata().ok(),
+                ))?;
+            } else {
+                visit(PlanEntry::Excluded)?;
+            }
+        }
+    } else if source.is_dir() {
+        for entry in options.builder(source).build() {
+            match entry {
+                Ok(dir_entry) => {
+                    let path = dir_entry.path();
+
+                    // Calculate relative path from source
+                    let relative_path = match path.strip_prefix(source) {
+                        Ok(p) if !p.as_os_str().is_empty() => p,
+                        _ => continue,
+                    };
+
+                    // Skip if blacklisted. Only files are counted as excluded,
+                    // the walk still reaches those inside an excluded folder.
+                    if filter.is_excluded(relative_path) || filter.is_excluded_absolute(path) {
+                        if !path.is_dir() {
+                            visit(PlanEntry::Excluded)?;
+                        }
+                        continue;
+                    }
+
+                    // Links are only seen here when they aren't being followed
+                    if dir_entry.path_is_symlink() && options.symlink_mode != SymlinkMode::Follow {
+                        if options.symlink_mode == SymlinkMode::Copy {
+                            visit(if filter.is_wanted(relative_path) {
+                                PlanEntry::Symlink {
+                                    source: path.to_path_buf(),
+                                    relative: relative_path.to_path_buf(),
+                                }
+                            } else {
+                                PlanEntry::Excluded
+                            })?;
+                        }
+                        continue;
+                    }
+
+                    if path.is_dir() {
+                        visit(PlanEntry::Dir {
+                            source: path.to_path_buf(),
+                            relative: relative_path.to_path_buf(),
+                        })?;
+                    } else if path.is_file() && filter.is_wanted(relative_path) {
+                        visit(sized_file_entry(
+                            filter,
+                            path,
+                            relative_path,
+                            dir_entry.metadata().ok(),
+                        ))?;
+                    } else if path.is_file() {
+                        visit(PlanEntry::Excluded)?;
+                    } else if let Ok(metadata) = path.metadata() {
+                        // Neither a file nor a directory
+                        if !filter.is_wanted(relative_path) {
+                            visit(PlanEntry::Excluded)?;
+                        } else if options.copy_special_files {
+                            visit(sized_file_entry(filter, path, relative_path, Some(metadata)))?;
+                        } else {
+                            visit(PlanEntry::Special {
+                                source: path.to_path_buf(),
+                                kind: special_file_kind(&metadata.file_type()),
+                            })?;
+                        }
+                    }
+                }
+                Err(e) => match unreadable_path(&e) {
+                    Some(path) => visit(PlanEntry::Unreadable {
+                        path: path.to_path_buf(),
+                    })?,
+                    None => visit(PlanEntry::Error(format!("Walker error: {}", e)))?,
+                },
+            }
+        }
+    }
+
+    ControlFlow::Continue(())
+}
+
+/// The entries to back up, either collected up front or produced by walking
+/// the sources while copying
+enum Plan<'a> {
+    Collected(Vec<PlanEntry>),
+    Streamed {
+        sources: &'a [Source<'a>],
+        options: &'a WalkOptions,
+    },
+}
+
+impl Plan<'_> {
+    /// Pass every entry to `execute` in order, stopping early on cancellation
+    fn run<R: Runtime>(self, ctx: &BackupContext<R>, execute: &mut dyn FnMut(PlanEntry)) {
+        match self {
+            Plan::Collected(entries) => {
+                for entry in entries {
+                    if ctx.is_cancelled() {
+                        break;
+                    }
+                    execute(ctx.review(entry));
+                }
+            }
+            Plan::Streamed { sources, options } => {
+                for source in sources {
+                    let path = Path::new(source.path);
+                    let flow = walk_source(path, source.filter, options, &mut |entry| {
+                        if ctx.is_cancelled() {
+                            return ControlFlow::Break(());
+                        }
+                        execute(ctx.review(entry));
+                        ControlFlow::Continue(())
+                    });
+                    if flow.is_break() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Carries out plan entries: creates directories in order and queues files
+/// for the copy workers, so parents always exist before their children.
+/// Every entry is replicated into each target.
+struct PlanExecutor<'a, R: Runtime> {
+    ctx: &'a BackupContext<'a, R>,
+    targets: &'a [PathBuf],
+    /// Trailing folder names of each source recreated in the target
+    source_depth: usize,
+    jobs: SyncSender<CopyJob>,
+    /// Destination root of the current source in each target, `None` where
+    /// it couldn't be created
+    roots: Vec<Option<PathBuf>>,
+    /// Copy every file directly into its target
+    flatten: bool,
+    /// How destination names are cleaned up, `None` to keep them as they are
+    names: Option<NameRules>,
+    /// Names chosen by the caller for single-file sources and files directly
+    /// inside a source, by canonical source path
+    name_overrides: HashMap<PathBuf, PathBuf>,
+    /// Destinations already handed out in flatten mode or when rewriting
+    /// names
+    claimed: HashSet<PathBuf>,
+    /// Number of sources started so far
+    sources_seen: usize,
+    /// Targets whose file system ignores case in file names
+    case_insensitive_targets: Vec<PathBuf>,
+    /// Destinations handed out below `case_insensitive_targets`, by their
+    /// lowercased path
+    folded: HashMap<PathBuf, PathBuf>,
+    /// Directories of the current source the walk is inside, outermost
+    /// first
+    walking_dirs: Vec<PathBuf>,
+    /// Bytes fill mode may still copy into each target
+    fill_budget: Option<u64>,
+    /// Whether entries arrive in the order of the walk, which finishes one
+    /// directory before moving on to the next
+    walk_order: bool,
+}
+
+impl<R: Runtime> PlanExecutor<'_, R> {
+    /// Destination roots of the current source that were created
+    fn active_roots(&self) -> Vec<PathBuf> {
+        self.roots.iter().flatten().cloned().collect()
+    }
+
+    /// Resolve where a file goes below `root`, or `None` if it's skipped
+    fn destination(&mut self, root: &Path, relative: &Path) -> Option<PathBuf> {
+        let dest_path = if self.flatten {
+            self.flat_destination(root, relative)?
+        } else if self.names.is_some() || !self.name_overrides.is_empty() {
+            // Rewriting can give different files the same name
+            self.claim_destination(root.join(relative), relative)?
+        } else {
+            root.join(relative)
+        };
+
+        if self.case_insensitive_targets.iter().any(|target| root.starts_with(target)) {
+            self.resolve_case_clash(dest_path, relative)
+        } else {
+            Some(dest_path)
+        }
+    }
+
+    /// Apply the collision mode to a destination whose name differs only in
+    /// case from another file of this run, which a case-insensitive target
+    /// would store in the same place. Returns `None` if the file is skipped.
+    fn resolve_case_clash(&mut self, dest_path: PathBuf, relative: &Path) -> Option<PathBuf> {
+        let ctx = self.ctx;
+        let other = match self.folded.get(&folded_path(&dest_path)) {
+            Some(other) if *other != dest_path => other.clone(),
+            _ => {
+                self.folded.insert(folded_path(&dest_path), dest_path.clone());
+                return Some(dest_path);
+            }
+        };
+
+        let clash = format!(
+            "Name differs only in case from {}, which the target treats as the same file",
+            other.to_string_lossy()
+        );
+        let dest_path = match ctx.collision_mode {
+            "rename" => {
+                let renamed = find_name_where(&dest_path, ctx.rename_template, |candidate| {
+                    self.folded.contains_key(&folded_path(candidate)) || candidate.exists()
+                });
+                ctx.report_warning(
+                    format!("{}, renamed to {}", clash, renamed.to_string_lossy()),
+                    &dest_path,
+                );
+                self.claimed.insert(renamed.clone());
+                renamed
+            }
+            "skip" | "newer" => {
+                ctx.report_warning(format!("{}, skipped", clash), &dest_path);
+                ctx.record_skip(&relative.to_string_lossy(), self.sources_seen - 1);
+                return None;
+            }
+            _ => {
+                ctx.report_warning(format!("{}, overwriting it", clash), &dest_path);
+                dest_path
+            }
+        };
+
+        self.folded.insert(folded_path(&dest_path), dest_path.clone());
+        Some(dest_path)
+    }
+
+    /// Pick the flattened destination of a file
+    fn flat_destination(&mut self, target: &Path, relative: &Path) -> Option<PathBuf> {
+        self.claim_destination(target.join(relative.file_name()?), relative)
+    }
+
+    /// Hand out a destination, resolving clashes between files of this run by
+    /// the collision mode. Clashes with files already in the target are left
+    /// to `copy_job`, except when renaming. Returns `None` if the file is
+    /// skipped.
+    fn claim_destination(&mut self, dest_path: PathBuf, relative: &Path) -> Option<PathBuf> {
+        let dest_path = if self.ctx.collision_mode == "rename" {
+            // Existing files are avoided here too, so workers renaming in
+            // parallel can't pick the same name
+            find_name_where(&dest_path, self.ctx.rename_template, |candidate| {
+                self.claimed.contains(candidate) || candidate.exists()
+            })
+        } else if self.claimed.contains(&dest_path)
+            && matches!(self.ctx.collision_mode, "skip" | "newer")
+        {
+            self.ctx.record_skip(&relative.to_string_lossy(), self.sources_seen - 1);
+            return None;
+        } else {
+            dest_path
+        };
+
+        self.claimed.insert(dest_path.clone());
+        Some(dest_path)
+    }
+
+    /// Mark every directory the walk was inside, but that doesn't contain
+    /// `next` as walked. The walk finishes a directory before moving on, so
+    /// none of them can get more files.
+    fn leave_dirs(&mut self, next: Option<&Path>) {
+        while let Some(dir) = self.walking_dirs.pop() {
+            if next.is_some_and(|next| next != dir && next.starts_with(&dir)) {
+                self.walking_dirs.push(dir);
+                break;
+            }
+            self.ctx.finish_dir_walk(self.sources_seen.saturating_sub(1), dir);
+        }
+    }
+
+    /// Mark the directories still being walked as done once the plan is over
+    fn finish(&mut self) {
+        self.leave_dirs(None);
+    }
+
+    /// Path of an entry below its destination root, with its name cleaned
+    /// up if requested. Renamed entries are remembered for the report.
+    fn dest_relative<'p>(&self, relative: &'p Path) -> Cow<'p, Path> {
+        let sanitized = clean_path(relative, self.names);
+        if sanitized.file_name() != relative.file_name() {
+            self.ctx.stats().renamed_entries.push((
+                relative.to_string_lossy().to_string(),
+                sanitized.to_string_lossy().to_string(),
+            ));
+        }
+        sanitized
+    }
+
+    fn execute(&mut self, entry: PlanEntry) {
+        let ctx = self.ctx;
+
+        match &entry {
+            PlanEntry::Root { .. } => self.leave_dirs(None),
+            PlanEntry::Dir { relative, .. }
+            | PlanEntry::File { relative, .. }
+            | PlanEntry::Symlink { relative, .. } => self.leave_dirs(Some(relative)),
+            _ => {}
+        }
+
+        match entry {
+            PlanEntry::Root { source } => {
+                self.sources_seen += 1;
+                self.roots = self
+                    .targets
+                    .iter()
+                    .map(|target| {
+                        if self.flatten || (!source.is_dir() && self.source_depth == 0) {
+                            return Some(target.clone());
+                        }
+
+                        let effective_target = effective_target(&source, target, self.source_depth);
+                        match create_dir(&effective_target, ctx.dry_run) {
+                            Ok(()) => {
+                                // Only a folder of its own takes the source's timestamp
+                                if self.source_depth > 0 && source.is_dir() {
+                                    ctx.track_dir(&source, &effective_target);
+                                }
+                                Some(effective_target)
+                            }
+                            Err(e) => {
+                                ctx.record_error(
+                                    format!("Failed to create target dir: {}", e),
+                                    Some(&effective_target),
+                                );
+                                None
+                            }
+                        }
+                    })
+                    .collect();
+            }
+            PlanEntry::Dir { .. } if self.flatten => {}
+            PlanEntry::Dir { source, relative } => {
+                if self.walk_order {
+                    let source_index = self.sources_seen - 1;
+                    let progress = DirProgress::default();
+                    ctx.stats().open_dirs.insert((source_index, relative.clone()), progress);
+                    self.walking_dirs.push(relative.clone());
+                }
+
+                let dest_relative = self.dest_relative(&relative);
+                let mut created = false;
+                for root in self.active_roots() {
+                    let dest_path = root.join(&dest_relative);
+                    ctx.keep_path(&dest_path);
+
+                    match create_dir(&dest_path, ctx.dry_run) {
+                        Ok(()) => {
+                            created = true;
+                            ctx.track_dir(&source, &dest_path);
+                        }
+                        Err(e) => ctx.report_error(e.to_string(), &dest_path),
+                    }
+                }
+
+                if created && ctx.move_files {
+                    ctx.stats().source_dirs.push(source);
+                }
+            }
+            PlanEntry::File {
+                source,
+                relative,
+                size,
+            } => {
+                let dest_relative = match name_override(&self.name_overrides, &source, &relative) {
+                    Some(name) => Cow::Owned(name),
+                    None => self.dest_relative(&relative),
+                };
+                let dest_relative = match ctx.encryption {
+                    Some(_) => Cow::Owned(encrypt::encrypted_name(&dest_relative)),
+                    None => dest_relative,
+                };
+
+                if ctx.fill_mode {
+                    let roots = self.active_roots();
+                    let growth = fill_growth(&roots, &dest_relative, size, ctx.collision_mode);
+                    let fits = self.fill_budget.is_none_or(|budget| budget >= growth);
+                    if !fits || ctx.is_target_full() {
+                        ctx.leave_out(&relative.to_string_lossy());
+                        return;
+                    }
+                    if let Some(budget) = &mut self.fill_budget {
+                        *budget -= growth;
+                    }
+                }
+
+                ctx.add_dir_file(self.sources_seen - 1, &relative);
+
+                for root in self.active_roots() {
+                    let Some(dest_path) = self.destination(&root, &dest_relative) else { continue };
+
+                    // Ensure parent directory exists
+                    if let Some(parent) = dest_path.parent() {
+                        if let Err(e) = create_dir(parent, ctx.dry_run) {
+                            ctx.record_error(
+                                format!("Failed to create parent dir: {}", e),
+                                Some(parent),
+                            );
+                            continue;
+                        }
+                    }
+
+                    ctx.keep_path(&dest_path);
+
+                    ctx.queue_job(
+                        &self.jobs,
+                        CopyJob {
+                            source: source.clone(),
+                            dest: dest_path,
+                            display_name: relative.to_string_lossy().to_string(),
+                            symlink: false,
+                            source_index: self.sources_seen - 1,
+                            dir: relative.parent().map(Path::to_path_buf),
+                        },
+                    );
+                }
+            }
+            PlanEntry::Symlink { source, relative } => {
+                if !cfg!(unix) {
+                    ctx.report_warning(
+                        "Symbolic links can't be recreated on this platform, skipped".to_string(),
+                        &source,
+                    );
+                    ctx.stats().skipped_count += self.targets.len() as u64;
+                    return;
+                }
+
+                ctx.add_dir_file(self.sources_seen - 1, &relative);
+                let dest_relative = self.dest_relative(&relative);
+                for root in self.active_roots() {
+                    let Some(dest_path) = self.destination(&root, &dest_relative) else { continue };
+                    ctx.keep_path(&dest_path);
+
+                    ctx.queue_job(
+                        &self.jobs,
+                        CopyJob {
+                            source: source.clone(),
+                            dest: dest_path,
+                            display_name: relative.to_string_lossy().to_string(),
+                            symlink: true,
+                            source_index: self.sources_seen - 1,
+                            dir: relative.parent().map(Path::to_path_buf),
+                        },
+                    );
+                }
+            }
+            PlanEntry::Special { source, kind } => {
+                ctx.report_warning(format!("Skipped {}", kind), &source)
+            }
+            PlanEntry::Unreadable { path } => ctx.record_unreadable(&path),
+            PlanEntry::Excluded => ctx.stats().excluded_count += self.targets.len() as u64,
+            PlanEntry::Error(message) => ctx.record_error(message, None),
+        }
+    }
+}
+
+/// How much copying a file of `size` bytes to `dest_relative` below each of
+/// `roots` adds to the fullest one. A file already there is replaced or
+/// skipped rather than copied again, so only the difference counts, unless
+/// renaming keeps both.
+fn fill_growth(roots: &[PathBuf], dest_relative: &Path, size: u64, collision_mode: &str) -> u64 {
+    roots
+        .iter()
+        .map(|root| match fs::metadata(long_path(&root.join(dest_relative))) {
+            Ok(_) if collision_mode == "rename" => size,
+            Ok(_) if collision_mode == "skip" => 0,
+            Ok(existing) => size.saturating_sub(existing.len()),
+            Err(_) => size,
+        })
+        .max()
+        .unwrap_or(size)
+}
+
+/// Write every planned entry into a fresh archive at the target instead of
+/// copying. In atomic mode the archive is assembled in a temporary file and
+/// only replaces the target once complete. Returns the size of the finished
+/// archive.
+fn write_archive<R: Runtime>(
+    ctx: &BackupContext<R>,
+    plan: Plan,
+    format: ArchiveFormat,
+    source_depth: usize,
+) -> Option<u64> {
+    let path = if ctx.atomic {
+        temp_path(ctx.target)
+    } else {
+        ctx.target.to_path_buf()
+    };
+
+    let mut writer = if ctx.dry_run {
+        None
+    } else {
+        match ArchiveWriter::create(&path, format) {
+            Ok(writer) => Some(writer),
+            Err(e) => {
+                ctx.record_error(format!("Failed to create archive: {}", e), Some(&path));
+                return None;
+            }
+        }
+    };
+
+    // Entry names are relative to the source, below its trailing folder
+    // names when `source_depth` is above 0
+    let mut prefix = PathBuf::new();
+    let mut sources_seen = 0;
+
+    plan.run(ctx, &mut |entry| match entry {
+        PlanEntry::Root { source } => {
+            sources_seen += 1;
+            prefix = source_folder(&source, source_depth);
+        }
+        PlanEntry::Dir { source, relative } => {
+            if let Some(writer) = writer.as_mut() {
+                if let Err(e) = writer.add_dir(&archive::entry_name(&prefix.join(&relative)), &source) {
+                    ctx.report_source_error(e.to_string(), &source, sources_seen - 1);
+                }
+            }
+        }
+        PlanEntry::File {
+            source,
+            relative,
+            size,
+        } => {
+            let name = archive::entry_name(&prefix.join(&relative));
+            let result = match writer.as_mut() {
+                Some(writer) => writer.add_file(&name, &source),
+                None => Ok(size),
+            };
+
+            match result {
+                Ok(bytes) => {
+                    let mut stats = ctx.stats();
+                    stats.copied_count += 1;
+                    stats.copied_bytes += bytes;
+                    stats.count_source(sources_seen - 1, bytes);
+                    stats.track_largest(&source, bytes, ctx.top_files);
+                    tracing::debug!(file = name, bytes, "Copied");
+                    ctx.emit_progress(&mut stats, &name, false);
+                }
+                Err(e) => ctx.report_source_error(e.to_string(), &source, sources_seen - 1),
+            }
+        }
+        PlanEntry::Symlink { source, relative } => {
+            let name = archive::entry_name(&prefix.join(&relative));
+            let result = fs::read_link(&source).and_then(|link| match writer.as_mut() {
+                Some(writer) => writer.add_symlink(&name, &link),
+                None => Ok(()),
+            });
+
+            match result {
+                Ok(()) => {
+                    let mut stats = ctx.stats();
+                    stats.copied_count += 1;
+                    stats.count_source(sources_seen - 1, 0);
+                    ctx.emit_progress(&mut stats, &name, false);
+                }
+                Err(e) => ctx.report_source_error(e.to_string(), &source, sources_seen - 1),
+            }
+        }
+        PlanEntry::Special { source, kind } => {
+            ctx.report_warning(format!("Skipped {}", kind), &source)
+        }
+        PlanEntry::Unreadable { path } => ctx.record_unreadable(&path),
+        PlanEntry::Excluded => ctx.stats().excluded_count += 1,
+        PlanEntry::Error(message) => ctx.record_error(message, None),
+    });
+
+    let writer = writer?;
+
+    // Don't let an incomplete archive replace the previous one
+    if ctx.atomic && ctx.is_cancelled() {
+        drop(writer);
+        let _ = fs::remove_file(&path);
+        return None;
+    }
+
+    let result = writer.finish().and_then(|_| {
+        if ctx.atomic {
+            fs::rename(&path, ctx.target)
+        } else {
+            Ok(())
+        }
+    });
+    if let Err(e) = result {
+        ctx.record_error(format!("Failed to write archive: {}", e), Some(ctx.target));
+        if ctx.atomic {
+            let _ = fs::remove_file(&path);
+        }
+        return None;
+    }
+
+    fs::metadata(ctx.target).ok().map(|m| m.len())
+}
+
+/// Decide where an upload goes when `dest` may already exist on the server,
+/// applying incremental mode and the collision mode. Returns `None` if the
+/// file is skipped, and otherwise whether the upload replaces the file there.
+fn remote_destination<R: Runtime>(
+    ctx: &BackupContext<R>,
+    session: &SftpSession,
+    source: &Path,
+    dest: PathBuf,
+) -> Option<(PathBuf, bool)> {
+    let Some(existing) = session.stat(&dest) else {
+        return Some((dest, false));
+    };
+
+    let metadata = fs::metadata(source).ok();
+    let source_mtime = metadata
+        .as_ref()
+        .and_then(|m| m.modified().ok())
+        .and_then(manifest::unix_seconds);
+    let unchanged = ctx.incremental
+        && existing.size == metadata.as_ref().map(|m| m.len())
+        && existing.mtime.is_some()
+        && existing.mtime == source_mtime;
+    if unchanged {
+        return None;
+    }
+
+    match ctx.collision_mode_for(source, &dest) {
+        "skip" => None,
+        // Uploaded unless both times are known and the source isn't newer
+        "newer" if is_newer_mtime(source_mtime, existing.mtime) == Some(false) => None,
+        "rename" => Some((
+            find_name_where(&dest, ctx.rename_template, |candidate| {
+                session.stat(candidate).is_some()
+            }),
+            false,
+        )),
+        _ => Some((dest, true)),
+    }
+}
+
+/// Upload every planned entry to the SFTP server below the remote target
+/// folder, one file at a time over the shared session
+fn upload_plan<R: Runtime>(ctx: &BackupContext<R>, plan: Plan, session: &SftpSession, source_depth: usize) {
+    // Remote paths always use `/`, whatever the local separator is
+    let remote_path = |name: &str| {
+        let base = ctx.target.to_string_lossy();
+        PathBuf::from(format!("{}/{}", base.trim_end_matches('/'), name))
+    };
+    let create_dir = |path: &Path| {
+        if ctx.dry_run {
+            return;
+        }
+        if let Err(e) = session.create_dir_all(path) {
+            ctx.record_error(format!("Failed to create remote dir: {}", e), Some(path));
+        }
+    };
+
+    let mut prefix = PathBuf::new();
+    let mut sources_seen = 0;
+
+    plan.run(ctx, &mut |entry| match entry {
+        PlanEntry::Root { source } => {
+            sources_seen += 1;
+            prefix = source_folder(&source, source_depth);
+            create_dir(&remote_path(&archive::entry_name(&prefix)));
+        }
+        PlanEntry::Dir { relative, .. } => {
+            create_dir(&remote_path(&archive::entry_name(&prefix.join(&relative))));
+        }
+        PlanEntry::File {
+            source,
+            relative,
+            size,
+        } => {
+            let name = archive::entry_name(&prefix.join(&relative));
+            let destination = remote_destination(ctx, session, &source, remote_path(&name));
+            let Some((dest, replaced)) = destination else {
+                ctx.record_skip(&name, sources_seen - 1);
+                return;
+            };
+
+            let result = if ctx.dry_run {
+                Ok(size)
+            } else {
+                session.upload(&source, &dest, ctx.preserve_timestamps, ctx.preserve_permissions)
+            };
+
+            match result {
+                Ok(bytes) => {
+                    let mut stats = ctx.stats();
+                    stats.copied_count += 1;
+                    stats.copied_bytes += bytes;
+                    stats.updated_count += replaced as u64;
+                    stats.count_source(sources_seen - 1, bytes);
+                    stats.track_largest(&source, bytes, ctx.top_files);
+                    tracing::debug!(file = name, bytes, "Copied");
+                    ctx.emit_progress(&mut stats, &name, false);
+                }
+                Err(e) => ctx.report_source_error(e.to_string(), &source, sources_seen - 1),
+            }
+        }
+        PlanEntry::Symlink { source, relative } => {
+            let name = archive::entry_name(&prefix.join(&relative));
+            let result = fs::read_link(&source).and_then(|link| {
+                if ctx.dry_run {
+                    Ok(())
+                } else {
+                    session.symlink(&link, &remote_path(&name))
+                }
+            });
+
+            match result {
+                Ok(()) => {
+                    let mut stats = ctx.stats();
+                    stats.copied_count += 1;
+                    stats.count_source(sources_seen - 1, 0);
+                    ctx.emit_progress(&mut stats, &name, false);
+                }
+                Err(e) => ctx.report_source_error(e.to_string(), &source, sources_seen - 1),
+            }
+        }
+        PlanEntry::Special { source, kind } => {
+            ctx.report_warning(format!("Skipped {}", kind), &source)
+        }
+        PlanEntry::Unreadable { path } => ctx.record_unreadable(&path),
+        PlanEntry::Excluded => ctx.stats().excluded_count += 1,
+        PlanEntry::Error(message) => ctx.record_error(message, None),
+    });
+}
+
+/// Canonical form of a path that may not exist yet, resolved through its
+/// deepest existing ancestor
+fn canonical_path(path: &Path) -> PathBuf {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut existing = absolute.as_path();
+    let mut missing = Vec::new();
+
+    loop {
+        if let Ok(mut canonical) = existing.canonicalize() {
+            // Nothing to follow below a missing folder, so `..` just goes up
+            for part in missing.iter().rev() {
+                match part {
+                    std::path::Component::ParentDir => {
+                        canonical.pop();
+                    }
+                    part => canonical.push(part),
+                }
+            }
+            return canonical;
+        }
+        match (existing.parent(), existing.components().next_back()) {
+            (Some(parent), Some(last)) => {
+                missing.push(last);
+                existing = parent;
+            }
+            _ => return absolute,
+        }
+    }
+}
+
+/// Reject source and target combinations that would copy something onto
+/// itself or delete a source, and return the target paths inside sources,
+/// which the walk has to skip so the backup doesn't recurse into itself
+fn check_overlap(
+    source_paths: &[String],
+    targets: &[PathBuf],
+    source_depth: usize,
+    mirror: bool,
+    archive_format: Option<ArchiveFormat>,
+) -> Result<Vec<PathBuf>, String> {
+    let mut skip_paths = Vec::new();
+
+    for target in targets {
+        let canonical_target = canonical_path(target);
+
+        for source_path in source_paths {
+            let source = Path::new(source_path);
+            let canonical_source = canonical_path(source);
+
+            if canonical_target == canonical_source {
+                return Err(format!("Target is the same as source: {}", source_path));
+            }
+
+            // Where the source itself ends up: its folder, or the file
+            let dest = effective_target(source, target, source_depth);
+            let dest = if source.is_dir() {
+                dest
+            } else {
+                dest.join(source.file_name().unwrap_or_default())
+            };
+            if archive_format.is_none() && canonical_path(&dest) == canonical_source {
+                return Err(format!("Source would be copied onto itself: {}", source_path));
+            }
+
+            if mirror && canonical_source.starts_with(&canonical_target) {
+                return Err(format!(
+                    "Mirror mode would delete source inside the target: {}",
+                    source_path
+                ));
+            }
+
+            let target_in_source = canonical_target.starts_with(&canonical_source);
+            if target_in_source && !skip_paths.contains(&canonical_target) {
+                skip_paths.push(canonical_target.clone());
+                if archive_format.is_some() {
+                    skip_paths.push(temp_path(&canonical_target));
+                }
+            }
+        }
+    }
+
+    Ok(skip_paths)
+}
+
+/// Folder name format of versioned backups, which also sorts chronologically
+const VERSION_FORMAT: &str = "%Y-%m-%d_%H-%M-%S";
+
+/// Delete the oldest version folders in `root` so that only the newest
+/// `keep`, including `current`, remain. Folders not named like a version are
+/// left alone.
+fn prune_versions<R: Runtime>(ctx: &BackupContext<R>, root: &Path, current: &Path, keep: usize) {
+    let Ok(entries) = fs::read_dir(root) else { return };
+
+    let mut versions: Vec<PathBuf> = entries
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+        .filter(|entry| {
+            let name = entry.file_name();
+            chrono::NaiveDateTime::parse_from_str(&name.to_string_lossy(), VERSION_FORMAT).is_ok()
+        })
+        .map(|entry| entry.path())
+        .collect();
+    versions.sort();
+
+    // A dry run hasn't created the current version
+    let mut keep = keep.max(1);
+    if !versions.iter().any(|version| version == current) {
+        keep -= 1;
+    }
+
+    let excess = versions.len().saturating_sub(keep);
+    for version in versions.into_iter().take(excess) {
+        let result = if ctx.dry_run {
+            Ok(())
+        } else {
+            fs::remove_dir_all(&version)
+        };
+
+        match result {
+            Ok(()) => {
+                let _ = ctx.app.emit(
+                    "backup-pruned",
+                    BackupPruned {
+                        path: version.to_string_lossy().to_string(),
+                    },
+                );
+            }
+            Err(e) => ctx.report_error(format!("Failed to prune old version: {}", e), &version),
+        }
+    }
+}
+
+/// Recursively copy directories/files using the `ignore` crate for fast traversal
+/// and glob-based blacklist filtering. Files are copied by a pool of
+/// `concurrency` worker threads (defaults to the number of logical CPUs).
+/// Progress events are sent at most once every `progress_interval`
+/// milliseconds (default 100).
+///
+/// The sources are walked once and the resulting plan is kept in memory, so
+/// the file count doesn't cost a second traversal. For huge trees where that
+/// list doesn't fit comfortably in memory, `two_pass` counts in a separate
+/// walk first and streams the copy walk instead.
+///
+/// With `include_source_dir` set, a source directory is copied into a folder
+/// of its own name in the target, and a single source file into a folder
+/// named after the directory containing it, so `photos/a.jpg` ends up at
+/// `<target>/photos/a.jpg`. Without it, both land directly in the target.
+/// `include_source_depth` recreates that many trailing folders of the path
+/// instead, so with 2 `/home/me/projects/app` is copied into
+/// `<target>/projects/app`; 0 and 1 are the same as leaving
+/// `include_source_dir` off and on. Paths with fewer folders use all of them.
+///
+/// With `incremental` set, files whose destination already has the same size
+/// and modification time are skipped. Use it together with
+/// `preserve_timestamps`, otherwise the comparison never matches.
+///
+/// `sync` keeps a target up to date with its sources in one step: it turns
+/// on `incremental` and makes an `overwrite` collision mode `newer`, so only
+/// files that are new or newer in a source are copied. Add `mirror` to also
+/// delete what was removed from the sources. `updated_count` tells how many
+/// of the copied files replaced an older version, next to `skipped_count`
+/// and `deleted_count`.
+///
+/// `preserve_timestamps` also covers directories: once every file is in
+/// place, each created directory gets the modified time of its source.
+///
+/// With `move_files` set, each source file is deleted once it has been copied
+/// (and verified, if `verify` is on). Files that were skipped or failed to
+/// copy are left in place.
+///
+/// `min_size` and `max_size` are inclusive bounds in bytes; files outside
+/// them are counted as skipped.
+///
+/// Unless `prefer_reflink` is false, files are cloned copy-on-write on
+/// filesystems that support it (Btrfs, XFS, APFS, ...), falling back to a
+/// regular copy elsewhere.
+///
+/// `collect_file_list` fills `BackupComplete::copied_files` with the
+/// destination of every copied file. It's off by default since the list can
+/// get very large.
+///
+/// Transient I/O errors (interrupted, timed out, would block) are retried up
+/// to `max_retries` times (default 3) with exponential backoff, emitting a
+/// `backup-retry` event for each attempt.
+///
+/// `max_depth` limits how far below each source folder the walk descends:
+/// 0 copies only its direct children. Deeper entries are neither copied nor
+/// counted.
+///
+/// `max_bytes_per_second` caps the combined throughput of all workers, and
+/// `file_timeout_seconds` abandons any single file copy that takes longer,
+/// reporting it as an error.
+///
+/// Files of 16 MB and more are copied through a buffer of `buffer_size`
+/// bytes (default 1 MB) instead of the platform copy; larger buffers tend to
+/// help with big files on fast drives.
+///
+/// When a backup runs to the end, a `backup-warning` lists the blacklist
+/// patterns that didn't match a single path, which is usually a typo.
+///
+/// `regex_blacklist` excludes paths matching any of its regular expressions,
+/// checked like glob patterns against the relative path (with `/`
+/// separators) and each of its components. An invalid expression fails the
+/// whole backup instead of being ignored.
+///
+/// `exclude_extensions` leaves out files by extension, such as `["tmp",
+/// ".log", "tar.gz"]`, with or without the dot and ignoring case, as a
+/// shorter way of writing `**/*.tmp` globs. Folders are never matched.
+///
+/// `respect_gitignore` applies `.gitignore` rules the way git does when the
+/// source is inside a repository, so a source that is a subfolder of one
+/// also honors the ignore files further up, such as the repository root's.
+/// Outside of a repository `.gitignore` files are not used. Without it, no
+/// ignore file above the source is read.
+///
+/// `absolute_blacklist` holds glob patterns matched against the full path of
+/// each entry, written with `/` on every platform, like `/home/*/.cache` or
+/// `C:/Users/*/AppData/Local/Temp`. A matching folder leaves out everything
+/// below it. This targets one place on disk where a `blacklist` pattern,
+/// matched relative to every source, could also catch folders of the same
+/// name in other sources.
+///
+/// With `collision_mode` set to `"hash"`, an existing file is only
+/// overwritten when its content differs from the source, whatever the
+/// timestamps say. Both files are hashed, so this is slower than `newer`.
+/// SFTP targets and encrypted backups can't be compared and are overwritten.
+///
+/// With `collision_mode` set to `"prompt"`, every file whose destination
+/// already exists is announced in a `backup-conflict` event and waits until
+/// the frontend answers through `resolve_conflict`. Clashes between files of
+/// the same run, as in `flatten` mode, are handled like `overwrite`.
+///
+/// With `interactive_filter` set, every file that passes the filters is
+/// announced in a `backup-review` event before it's copied, and the backup
+/// waits until the frontend answers through `approve_file` or `reject_file`.
+/// Rejected files are left out as if filtered. Either answer can be given
+/// with `rest` to decide the remaining files of the run without asking.
+///
+/// In `rename` mode, `rename_template` picks the new names, for example
+/// `"{stem} ({n}){ext}"` or `"{stem}_{timestamp}{ext}"`. It may use `{stem}`,
+/// `{ext}` (including its dot), a counter `{n}` and the local `{timestamp}`,
+/// and needs `{n}` or `{timestamp}`. Without it files are renamed to
+/// `name_1.txt`, `name_2.txt` and so on.
+///
+/// With `flatten` set, every file is copied directly into the target folder
+/// and no directories are recreated, so `include_source_dir` has no effect.
+/// Files from different folders that end up with the same name are resolved
+/// by `collision_mode`; `newer` keeps the first file seen.
+///
+/// `source_specs` adds sources that carry their own `blacklist` and
+/// `whitelist`, each falling back to the global list when left out. They are
+/// backed up after the plain `source_paths`, which use the global lists.
+///
+/// On targets that ignore case in file names, files whose destinations differ
+/// only in case, like `File.txt` and `file.txt`, would overwrite each other.
+/// Such clashes are reported as warnings and resolved by `collision_mode`.
+///
+/// Every file is also copied into each of the `extra_targets`, with progress
+/// counting each copy separately. A target that fails doesn't stop the
+/// others; its errors name the destination they occurred in.
+///
+/// With `archive` set to `"zip"` or `"targz"`, `target_path` names an
+/// archive file that receives every file under its relative path instead of
+/// a folder tree. Filtering works as usual, while collision handling,
+/// mirroring, moving, flattening, versioning and extra targets don't apply.
+///
+/// With `dedupe` set, every file is hashed and files whose content was
+/// already copied in this run are hardlinked to that copy. Where hardlinks
+/// aren't possible, such as across devices, the file is copied normally.
+///
+/// Following symlinks already enters each real directory only once per
+/// source, but files can still be reached through several links or from
+/// several sources. With `deduplicate_symlinks` set, a file whose real path
+/// was already copied in this run is hardlinked to that copy instead, with
+/// the same fallback as `dedupe`.
+///
+/// With `resume_partial` set and `atomic` off, a destination file shorter
+/// than its source is taken to be an interrupted copy and continued from its
+/// end instead of copied again, provided its last megabyte matches the
+/// source. Failed attempts are continued the same way when retried. Verify to
+/// be sure the result matches, since only that last part is compared first.
+///
+/// With `versioned` set, each run copies into a new `YYYY-MM-DD_HH-MM-SS`
+/// folder below the target. `keep_versions` then limits how many of those
+/// folders are kept: once a run finishes without errors, the oldest ones
+/// beyond the limit are deleted and reported as `backup-pruned` events.
+///
+/// `modified_after` and `modified_before` limit the backup to files modified
+/// in that window, given as Unix epoch seconds. Those are UTC, so convert
+/// local dates before passing them. `modified_after` is inclusive and
+/// `modified_before` exclusive; files outside the window count as skipped.
+///
+/// With `encrypt` set to a passphrase, every file is encrypted with a key
+/// derived from it and stored as `<name>.enc`. Encrypted copies can't be
+/// verified, so `verify` is turned off with a `backup-warning`. Since an
+/// encrypted copy never matches its source, `encrypt` can't be combined with
+/// `incremental`, and a manifest written for it can't be used to restore the
+/// backup. Archives and SFTP targets can't be encrypted.
+///
+/// A `target_path` of the form `sftp://user@host[:port]/path` uploads the
+/// backup to that folder on an SSH server. The server has to be listed in
+/// `~/.ssh/known_hosts`. `sftp_key_path` logs in with a private key, unlocked
+/// by `sftp_password` if needed, while `sftp_password` alone logs in with a
+/// password; with neither the SSH agent is used. Filtering, collision
+/// handling and incremental mode work as usual, while mirroring, moving,
+/// flattening, versioning, reports and extra targets don't apply.
+///
+/// The result lists the `top_files` largest copied files, 10 by default, to
+/// show what takes up most of the backup.
+///
+/// `per_source` breaks the totals down by source path, in the order given,
+/// with the files copied, skipped at their destination and failed and the
+/// bytes copied from each.
+///
+/// `max_errors` stops the backup once that many errors occurred, sending a
+/// `backup-aborted` event instead of going through every remaining file.
+///
+/// `strict` is for backups that should either be complete or clearly fail:
+/// the first error, after its `backup-error` event, stops the run like a
+/// `max_errors` of 1, and the result has `success` false. Files copied
+/// before the error are kept.
+///
+/// With `sanitize_names` set, characters that Windows or cloud services don't
+/// allow in names (`<>:"|?*\\` and control characters) are replaced by `_`,
+/// trailing dots and spaces are dropped and reserved names like `CON` get a
+/// `_` appended, and `lowercase_names` also lowercases every name. This
+/// applies to folder targets. Files that end up with the same name are
+/// handled by `collision_mode`, and the report lists every renamed entry.
+///
+/// `normalize_unicode` gives every destination name the `"nfc"` or `"nfd"`
+/// form, so a backup made on macOS, which stores names decomposed, and one
+/// made on Linux don't end up with two copies of the same file. As with
+/// sanitizing, files whose names become equal go through `collision_mode`.
+///
+/// `link_dest` takes the root of an earlier backup, such as the previous
+/// version folder, for snapshots that only store what changed. A file whose
+/// copy there has the same size and modification time as the source is
+/// hardlinked to that copy instead of being copied again, as long as both
+/// are on the same volume. Only the main target uses it.
+///
+/// With `use_hash_cache` set, the content hashes computed for `verify`, the
+/// `hash` collision mode, `dedupe` and manifests are kept in a
+/// `.m4ssc0py-hashes.json` file in the target. Later runs reuse the hash of
+/// every file whose size and modification time are unchanged instead of
+/// reading it again. A copied file is still read back to be verified.
+///
+/// `name_overrides` maps source files to the names they get in the target,
+/// for single-file sources and files directly inside a source folder, such
+/// as `{"/home/me/notes.txt": "notes-2024.txt"}`. The names are used as
+/// given, without `sanitize_names`, and must not contain a path separator.
+/// If an overridden name is taken, by an existing file or by another file of
+/// this run, `collision_mode` decides as usual. Archives and SFTP targets
+/// keep the original names.
+///
+/// `temp_dir` moves the temporary files of atomic copies out of the target
+/// folders, for example off a slow drive. It has to be on the same volume as
+/// the targets for the final rename to be atomic; otherwise a warning is sent
+/// and the files are staged next to their destination as usual. Temporary
+/// files an interrupted run left in it, or next to their destinations in a
+/// folder target, are removed when a backup starts.
+///
+/// `sort_order` copies the files of each source by `"name"`, by `"size"`
+/// (smallest first) or by `"mtime"` (oldest first) instead of in the order
+/// the file system lists them (`"none"`, the default), so progress is even
+/// and archives come out the same for the same files. Sorting needs the
+/// whole file list in memory, roughly a few hundred bytes per file, so
+/// `two_pass` is ignored while sorting.
+///
+/// `fill_mode` is for targets smaller than the sources. Files are copied
+/// newest first, within each source, for as long as they fit into the free
+/// space the targets had at the start. A file already in the target only
+/// counts with what it grows by. Once a file doesn't fit or a write fails
+/// for lack of space, the remaining files are left out rather than reported
+/// as errors, counted in `left_out_count` and listed in the report. Other
+/// errors are still reported. It needs a folder target, can't be combined
+/// with `mirror`, and collects every file up front, so `two_pass` is ignored.
+///
+/// Files in the target that aren't part of the backup are never touched
+/// outside of mirror mode. With `fail_on_existing_foreign` set the backup
+/// refuses to start if there are any, and its error lists them, so an
+/// important folder picked as the target by mistake stays as it was.
+///
+/// When copying into folders, a `backup-dir-complete` event is sent for each
+/// source subdirectory once every file below it has been handled, with the
+/// number of files it held.
+///
+/// Besides the events, every run is recorded in a daily `backup.<date>.log`
+/// in the app's log folder, of which the last 14 are kept. `log_level` is
+/// one of `error`, `warn`, `info` (the default, with the settings and the
+/// outcome), `debug` (also every copied and skipped file) or `trace`.
+///
+/// With `write_report` set, a human-readable `backup_report.txt` is written
+/// into the target once the run finishes, listing the times, counts, files
+/// copied per source, the settings used and every error. In archive mode it
+/// goes next to the archive. Dry runs don't write a report.
+///
+/// Named pipes, sockets and devices are skipped with a warning, since reading
+/// them could block the backup or fail. With `copy_special_files` set they
+/// are read like regular files instead, so a pipe is only copied once a
+/// writer closes it.
+///
+/// Hidden files and folders, those whose name starts with a dot or, on
+/// Windows, that carry the hidden attribute, are backed up unless
+/// `include_hidden` is false. Sources named directly are always included.
+///
+/// With `preserve_xattr` set, extended attributes are copied along with each
+/// file on Linux and macOS, and alternate data streams on Windows. Other
+/// platforms, archives and SFTP targets don't keep them. Attributes that
+/// can't be copied, such as those the file system doesn't support, are
+/// reported as warnings.
+///
+/// With `preserve_creation_time` set, the creation time of each copied file
+/// is kept as well on Windows and macOS. Linux and other platforms can't set
+/// it, which is reported once as a warning. Archives and SFTP targets don't
+/// keep it.
+///
+/// Only problems found before anything is copied, like a missing source, a
+/// target that can't be created or invalid settings, are returned as `Err`.
+/// Once copying has started, every failure is recorded in the result, which
+/// then has `success` false along with the counts of everything done so far
+/// and the errors that occurred.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn backup_directory<R: Runtime>(
+    app: AppHandle<R>,
+    control: State<'_, BackupControl>,
+    source_paths: Vec<String>,
+    target_path: String,
+    blacklist: Vec<String>,
+    whitelist: Vec<String>,
+    respect_gitignore: bool,
+    include_source_dir: bool,
+    collision_mode: String,
+    dry_run: bool,
+    concurrency: Option<usize>,
+    preserve_timestamps: bool,
+    incremental: bool,
+    verify: bool,
+    mirror: bool,
+    progress_interval: Option<u64>,
+    two_pass: bool,
+    case_insensitive: Option<bool>,
+    symlink_mode: String,
+    atomic: bool,
+    manifest_path: Option<String>,
+    move_files: bool,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    prefer_reflink: Option<bool>,
+    respect_backupignore: Option<bool>,
+    preserve_permissions: bool,
+    collect_file_list: Option<bool>,
+    max_retries: Option<u32>,
+    max_depth: Option<usize>,
+    max_bytes_per_second: Option<u64>,
+    flatten: bool,
+    extra_targets: Option<Vec<String>>,
+    archive: Option<String>,
+    dedupe: bool,
+    file_timeout_seconds: Option<u64>,
+    regex_blacklist: Vec<String>,
+    versioned: bool,
+    keep_versions: Option<usize>,
+    write_report: bool,
+    modified_after: Option<i64>,
+    modified_before: Option<i64>,
+    buffer_size: Option<usize>,
+    source_specs: Option<Vec<SourceSpec>>,
+    sftp_password: Option<String>,
+    sftp_key_path: Option<String>,
+    encrypt: Option<String>,
+    preserve_xattr: bool,
+    include_hidden: Option<bool>,
+    copy_special_files: bool,
+    rename_template: Option<String>,
+    deduplicate_symlinks: bool,
+    top_files: Option<usize>,
+    resume_partial: bool,
+    log_level: Option<String>,
+    max_errors: Option<usize>,
+    include_source_depth: Option<usize>,
+    fail_on_existing_foreign: bool,
+    sanitize_names: bool,
+    lowercase_names: Option<bool>,
+    normalize_unicode: Option<String>,
+    fill_mode: bool,
+    temp_dir: Option<String>,
+    exclude_extensions: Vec<String>,
+    use_hash_cache: bool,
+    link_dest: Option<String>,
+    sort_order: Option<String>,
+    interactive_filter: bool,
+    preserve_creation_time: bool,
+    sync: bool,
+    absolute_blacklist: Vec<String>,
+    strict: bool,
+    name_overrides: Option<HashMap<String, String>>,
+) -> Result<BackupComplete, String> {
+    let started = chrono::Local::now();
+
+    // Sources with their own filters follow the plain ones
+    let source_specs = source_specs.unwrap_or_default();
+    let mut source_paths = source_paths;
+    source_paths.extend(source_specs.iter().map(|spec| spec.path.clone()));
+
+    // How many trailing folders of each source are recreated in the target
+    let source_depth = include_source_depth.unwrap_or(include_source_dir as usize);
+
+    let name_rules = NameRules {
+        sanitize: sanitize_names,
+        lowercase: sanitize_names && lowercase_names.unwrap_or(false),
+        unicode: match normalize_unicode.as_deref() {
+            Some(form) => Some(
+                UnicodeForm::parse(form)
+                    .ok_or_else(|| format!("Unknown Unicode normalization: {}", form))?,
+            ),
+            None => None,
+        },
+    };
+    let names = (name_rules.sanitize || name_rules.unicode.is_some()).then_some(name_rules);
+
+    // Sync mode only copies what is new or newer than the target's copy
+    let incremental = incremental || sync;
+    let collision_mode = match collision_mode.as_str() {
+        "overwrite" if sync => "newer".to_string(),
+        _ => collision_mode,
+    };
+    // Strict mode gives up on the first error
+    let max_errors = if strict { Some(1) } else { max_errors };
+
+    // Reset the cancellation flag so a previous cancel doesn't affect this run
+    control.reset();
+
+    if let Some(log) = app.try_state::<BackupLog>() {
+        log.set_level(log_level.as_deref())?;
+    }
+    tracing::info!(
+        sources = ?source_paths,
+        target = %target_path,
+        collision_mode = %collision_mode,
+        dry_run,
+        incremental,
+        sync,
+        mirror,
+        verify,
+        "Backup started"
+    );
+
+    // Validate we have sources
+    if source_paths.is_empty() {
+        return Err("No source paths provided".to_string());
+    }
+
+    // Validate all sources exist
+    for source_path in &source_paths {
+        let source = Path::new(source_path);
+        if !source.exists() {
+            return Err(format!("Source path does not exist: {}", source_path));
+        }
+    }
+
+    let archive_format = match archive.as_deref() {
+        Some(format) => Some(
+            ArchiveFormat::parse(format).ok_or_else(|| format!("Unsupported archive format: {}", format))?,
+        ),
+        None => None,
+    };
+    if let Some(template) = &rename_template {
+        validate_rename_template(template)?;
+    }
+    let overrides = match &name_overrides {
+        Some(overrides) => parse_name_overrides(overrides)?,
+        None => HashMap::new(),
+    };
+    if let Some(link_dest) = &link_dest {
+        if !Path::new(link_dest).is_dir() {
+            return Err(format!("Previous backup folder does not exist: {}", link_dest));
+        }
+    }
+
+    // An `sftp://` target is uploaded to instead of written locally
+    let remote = RemoteTarget::parse(&target_path).transpose()?;
+    let session = match &remote {
+        Some(_) if archive_format.is_some() => {
+            return Err("Archives can't be written to an SFTP target".to_string());
+        }
+        Some(remote) => Some(SftpSession::connect(
+            remote,
+            &Credentials {
+                password: sftp_password.as_deref(),
+                key_path: sftp_key_path.as_deref().map(Path::new),
+            },
+        )?),
+        None => None,
+    };
+    let encryption = match encrypt.as_deref() {
+        Some(_) if archive_format.is_some() || remote.is_some() => {
+            return Err("Encryption is only supported for folder targets".to_string());
+        }
+        // An encrypted copy never matches its source, so nothing would be skipped
+        Some(_) if incremental => {
+            return Err("Incremental backups can't be encrypted".to_string());
+        }
+        Some(passphrase) => Some(FileEncryptor::new(passphrase)?),
+        None => None,
+    };
+    let versions_root = match &remote {
+        Some(remote) => remote.path.as_path(),
+        None => Path::new(&target_path),
+    };
+
+    // Versioned backups go into a new timestamped folder below each target
+    let version_name = (versioned && archive_format.is_none() && remote.is_none())
+        .then(|| chrono::Local::now().format(VERSION_FORMAT).to_string());
+    let version_dir;
+    let target = match &version_name {
+        Some(version_name) => {
+            version_dir = versions_root.join(version_name);
+            version_dir.as_path()
+        }
+        None => versions_root,
+    };
+
+    // Create target directory if it doesn't exist. In archive mode the target
+    // is the archive file itself.
+    let target_dir = match archive_format {
+        Some(_) => target.parent().unwrap_or(Path::new("")),
+        None => target,
+    };
+    if remote.is_none() {
+        if let Err(e) = create_dir(target_dir, dry_run) {
+            return Err(format!("Failed to create target directory: {}", e));
+        }
+    }
+
+    // Additional targets that can't be created are reported and left out
+    let mut targets = vec![target.to_path_buf()];
+    let mut target_errors: Vec<(String, PathBuf)> = Vec::new();
+    let local_folder = archive_format.is_none() && remote.is_none();
+    for extra_target in extra_targets.filter(|_| local_folder).unwrap_or_default() {
+        let extra_target = match &version_name {
+            Some(version_name) => Path::new(&extra_target).join(version_name),
+            None => PathBuf::from(extra_target),
+        };
+        if targets.contains(&extra_target) {
+            continue;
+        }
+        match create_dir(&extra_target, dry_run) {
+            Ok(()) => targets.push(extra_target),
+            Err(e) => target_errors.push((
+                format!("Failed to create target directory: {}", e),
+                extra_target,
+            )),
+        }
+    }
+
+    // Build glob sets from blacklist and whitelist patterns
+    // Patterns match case-insensitively on Windows unless told otherwise
+    let case_insensitive = case_insensitive.unwrap_or(cfg!(windows));
+    let build_filter = |blacklist: &[String], whitelist: &[String]| {
+        FileFilter::new(blacklist, whitelist, case_insensitive)
+            .with_size_limits(min_size, max_size)
+            .with_modified_window(modified_after, modified_before)
+            .with_excluded_extensions(&exclude_extensions)
+            .with_absolute_blacklist(&absolute_blacklist, case_insensitive)
+            .with_regex_blacklist(&regex_blacklist, case_insensitive)
+    };
+    let filter = build_filter(&blacklist, &whitelist)?;
+    let spec_filters = source_specs
+        .iter()
+        .map(|spec| {
+            build_filter(
+                spec.blacklist.as_deref().unwrap_or(&blacklist),
+                spec.whitelist.as_deref().unwrap_or(&whitelist),
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    for warning in spec_filters.iter().chain([&filter]).flat_map(|f| &f.warnings) {
+        let _ = app.emit(
+            "backup-warning",
+            BackupWarning {
+                message: warning.clone(),
+                file: None,
+            },
+        );
+    }
+    if preserve_creation_time && !CREATION_TIME_SUPPORTED {
+        let message = "Creation times can't be set on this platform and won't be preserved";
+        tracing::warn!("{}", message);
+        let _ = app.emit(
+            "backup-warning",
+            BackupWarning {
+                message: message.to_string(),
+                file: None,
+            },
+        );
+    }
+    if verify && encryption.is_some() {
+        let message = "Encrypted copies can't be verified, so verification is turned off";
+        tracing::warn!("{}", message);
+        let _ = app.emit(
+            "backup-warning",
+            BackupWarning {
+                message: message.to_string(),
+                file: None,
+            },
+        );
+    }
+    if fill_mode && !local_folder {
+        return Err("Fill mode only works with folder targets".to_string());
+    }
+    // Mirroring would delete the earlier copies of every file left out
+    if fill_mode && mirror {
+        return Err("Fill mode can't be combined with mirror".to_string());
+    }
+    // Fill mode copies the newest files first, whatever `sort_order` says
+    let sort_order = match fill_mode {
+        true => Some(SortOrder::NewestFirst),
+        false => SortOrder::parse(sort_order.as_deref().unwrap_or("none"))?,
+    };
+    // Sorting needs every entry up front
+    let two_pass = two_pass && sort_order.is_none();
+
+    let plain_count = source_paths.len() - spec_filters.len();
+    let sources: Vec<Source> = source_paths
+        .iter()
+        .enumerate()
+        .map(|(i, path)| Source {
+            path,
+            filter: i.checked_sub(plain_count).map_or(&filter, |spec| &spec_filters[spec]),
+        })
+        .collect();
+    // Targets inside a source are left out of the walk
+    let mut skip_paths = if remote.is_some() {
+        Vec::new()
+    } else {
+        check_overlap(&source_paths, &targets, source_depth, mirror, archive_format)?
+    };
+    let temp_dir = match temp_dir.filter(|_| atomic && local_folder && !dry_run) {
+        Some(temp_dir) => {
+            let temp_dir = PathBuf::from(temp_dir);
+            match prepare_temp_dir(&temp_dir, &targets) {
+                Ok(()) => {
+                    skip_paths.extend(temp_dir.canonicalize());
+                    Some(temp_dir)
+                }
+                Err(message) => {
+                    let _ = app.emit(
+                        "backup-warning",
+                        BackupWarning {
+                            message,
+                            file: Some(temp_dir.to_string_lossy().to_string()),
+                        },
+                    );
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+    // Before anything looks at what's already in the targets
+    if local_folder && !dry_run {
+        for target in &targets {
+            remove_stale_temp_files(target);
+        }
+    }
+    let walk_options = WalkOptions {
+        include_hidden: include_hidden.unwrap_or(true),
+        respect_gitignore,
+        respect_backupignore: respect_backupignore.unwrap_or(true),
+        symlink_mode: SymlinkMode::parse(&symlink_mode),
+        max_depth,
+        skip_paths,
+        copy_special_files,
+    };
+
+    if fail_on_existing_foreign && local_folder {
+        let foreign = find_foreign_files(
+            &sources,
+            &targets,
+            &walk_options,
+            source_depth,
+            flatten,
+            // Named like the copy names them, where only files are encrypted
+            &|entry| match entry {
+                PlanEntry::File {
+                    source, relative, ..
+                } => {
+                    let name = name_override(&overrides, source, relative)
+                        .unwrap_or_else(|| clean_path(relative, names).into_owned());
+                    match encryption {
+                        Some(_) => Some(encrypt::encrypted_name(&name)),
+                        None => Some(name),
+                    }
+                }
+                PlanEntry::Symlink { relative, .. } => {
+                    Some(clean_path(relative, names).into_owned())
+                }
+                _ => None,
+            },
+        );
+        if !foreign.is_empty() {
+            let mut message = format!(
+                "The target already contains {} files that aren't part of this backup:",
+                foreign.len()
+            );
+            for path in foreign.iter().take(MAX_REPORTED_ERRORS) {
+                message.push_str(&format!("\n{}", path.to_string_lossy()));
+            }
+            if foreign.len() > MAX_REPORTED_ERRORS {
+                let more = foreign.len() - MAX_REPORTED_ERRORS;
+                message.push_str(&format!("\n... and {} more", more));
+            }
+            return Err(message);
+        }
+    }
+
+    // Plan the backup: either collect every entry in a single walk, or just
+    // count files and bytes here and walk again while copying
+    let mut emit_counting = |found_count, found_bytes| {
+        let _ = app.emit(
+            "backup-counting",
+            BackupCounting {
+                found_count,
+                found_bytes,
+            },
+        );
+    };
+    let mut plan: Vec<PlanEntry> = Vec::new();
+    let (total_count, total_bytes) = if two_pass {
+        count_files_multi(&sources, &walk_options, &mut emit_counting)
+    } else {
+        walk_sources(&sources, &walk_options, &mut emit_counting, &mut |entry| {
+            plan.push(entry);
+            if control.cancelled.load(Ordering::SeqCst) {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        })
+    };
+    if let Some(order) = sort_order {
+        sort_plan(&mut plan, order);
+    }
+    let plan = if two_pass {
+        Plan::Streamed {
+            sources: &sources,
+            options: &walk_options,
+        }
+    } else {
+        Plan::Collected(plan)
+    };
+
+    let _ = app.emit(
+        "backup-start",
+        BackupStart {
+            total_count,
+            total_bytes,
+            target: target
+                .canonicalize()
+                .unwrap_or_else(|_| target.to_path_buf())
+                .to_string_lossy()
+                .to_string(),
+            source_count: source_paths.len(),
+        },
+    );
+    let copy_started = Instant::now();
+
+    let workers = concurrency
+        .or_else(|| thread::available_parallelism().map(|n| n.get()).ok())
+        .unwrap_or(1)
+        .max(1);
+
+    let ctx = BackupContext {
+        app: &app,
+        control: &control,
+        target,
+        collision_mode: &collision_mode,
+        interactive_filter,
+        rename_template: rename_template.as_deref(),
+        dry_run,
+        preserve_timestamps,
+        preserve_permissions,
+        preserve_xattr,
+        preserve_creation_time: preserve_creation_time && CREATION_TIME_SUPPORTED,
+        incremental,
+        verify,
+        mirror,
+        atomic,
+        move_files,
+        prefer_reflink: prefer_reflink.unwrap_or(true),
+        dedupe,
+        deduplicate_symlinks,
+        resume_partial,
+        temp_dir,
+        hash_cache: (use_hash_cache && local_folder)
+            .then(|| HashCache::load(&target.join(HASH_CACHE_FILENAME))),
+        link_dest: link_dest.clone().map(PathBuf::from).filter(|_| local_folder),
+        fill_mode,
+        encryption,
+        max_retries: max_retries.unwrap_or(3),
+        max_errors: max_errors.map(|max| max as u64),
+        chunked: ChunkedCopy {
+            limiter: max_bytes_per_second.map(|rate| Arc::new(RateLimiter::new(rate))),
+            timeout: file_timeout_seconds.map(Duration::from_secs),
+            buffer_size: buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE),
+        },
+        manifest: manifest_path.is_some(),
+        collect_file_list: collect_file_list.unwrap_or(false),
+        top_files: top_files.unwrap_or(DEFAULT_TOP_FILES),
+        progress_interval: Duration::from_millis(progress_interval.unwrap_or(100)),
+        total_count: total_count * targets.len() as u64,
+        total_bytes: total_bytes * targets.len() as u64,
+        multiple_targets: targets.len() > 1,
+        stats: Mutex::new(BackupStats::default()),
+    };
+
+    for (message, extra_target) in target_errors {
+        ctx.record_error(message, Some(&extra_target));
+    }
+    if ctx.hash_cache.is_some() {
+        ctx.keep_path(&target.join(HASH_CACHE_FILENAME));
+    }
+
+    let mut archive_size = None;
+    if let Some(session) = &session {
+        upload_plan(&ctx, plan, session, source_depth);
+    } else if let Some(format) = archive_format {
+        archive_size = write_archive(&ctx, plan, format, source_depth);
+    } else {
+        let (jobs, queue) = mpsc::sync_channel::<CopyJob>(workers * 4);
+        let queue = Mutex::new(queue);
+
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    let job = queue.lock().unwrap_or_else(|e| e.into_inner()).recv();
+                    match job {
+                        Ok(mut job) => {
+                            let source_index = job.source_index;
+                            let dir = job.dir.take();
+                            copy_job(&ctx, job);
+                            if let Some(dir) = dir {
+                                ctx.finish_dir_job(source_index, &dir);
+                            }
+                        }
+                        Err(_) => break,
+                    }
+                });
+            }
+
+            let mut executor = PlanExecutor {
+                ctx: &ctx,
+                targets: &targets,
+                source_depth,
+                jobs,
+                roots: Vec::new(),
+                flatten,
+                names,
+                name_overrides: overrides,
+                claimed: HashSet::new(),
+                sources_seen: 0,
+                case_insensitive_targets: targets
+                    .iter()
+                    .filter(|target| is_case_insensitive_dir(target, dry_run))
+                    .cloned()
+                    .collect(),
+                folded: HashMap::new(),
+                walking_dirs: Vec::new(),
+                walk_order: matches!(sort_order, None | Some(SortOrder::Name)),
+                fill_budget: fill_mode
+                    .then(|| targets.iter().filter_map(|target| available_space(target)).min())
+                    .flatten(),
+            };
+
+            plan.run(&ctx, &mut |entry| executor.execute(entry));
+            executor.finish();
+
+            // Closing the channel lets workers exit once the queue drains
+            drop(executor);
+        });
+    }
+
+    ctx.flush_progress();
+
+    // A pattern that matched nothing in a complete run is most likely a typo
+    if !ctx.is_cancelled() {
+        let unused = unused_patterns(spec_filters.iter().chain([&filter]));
+        if !unused.is_empty() {
+            let message = format!("Blacklist patterns that matched nothing: {}", unused.join(", "));
+            tracing::warn!("{}", message);
+            let _ = app.emit("backup-warning", BackupWarning { message, file: None });
+        }
+    }
+
+    // Mirror mode: remove target entries that no longer exist in the sources.
+    // A cancelled run hasn't seen every source entry, so nothing is deleted.
+    if mirror && local_folder && !ctx.is_cancelled() {
+        let mut roots: Vec<PathBuf> = Vec::new();
+        for target in &targets {
+            for source_path in &source_paths {
+                let source = Path::new(source_path);
+                if source.is_dir() {
+                    let root = effective_target(source, target, source_depth);
+                    if !roots.contains(&root) {
+                        roots.push(root);
+                    }
+                }
+            }
+        }
+
+        for root in &roots {
+            mirror_target(&ctx, root);
+        }
+    }
+
+    if move_files && !dry_run && remote.is_none() {
+        remove_empty_source_dirs(&ctx);
+    }
+
+    // Last, so nothing else touches the directories afterwards
+    restore_dir_mtimes(&ctx);
+
+    // Old versions are only pruned once a new one completed without problems
+    if let (Some(_), Some(keep)) = (&version_name, keep_versions) {
+        if !ctx.is_cancelled() && ctx.stats().error_count == 0 {
+            for target in &targets {
+                if let Some(root) = target.parent() {
+                    prune_versions(&ctx, root, target, keep);
+                }
+            }
+        }
+    }
+
+    if let Some(manifest_path) = manifest_path.filter(|_| !dry_run) {
+        let files = std::mem::take(&mut ctx.stats().manifest);
+        let manifest_path = PathBuf::from(manifest_path);
+        let manifest = Manifest::new(target, files, ctx.encryption.is_some());
+        if let Err(e) = manifest.write(&manifest_path) {
+            ctx.record_error(
+                format!("Failed to write manifest: {}", e),
+                Some(&manifest_path),
+            );
+        }
+    }
+
+    if let Some(cache) = ctx.hash_cache.as_ref().filter(|_| !dry_run) {
+        if let Err(e) = cache.save() {
+            let path = target.join(HASH_CACHE_FILENAME);
+            ctx.record_error(format!("Failed to write hash cache: {}", e), Some(&path));
+        }
+    }
+
+    let elapsed = copy_started.elapsed();
+    let BackupStats {
+        copied_count,
+        copied_bytes,
+        skipped_count: skipped_collision,
+        excluded_count: excluded_filter,
+        unreadable_count: skipped_unreadable,
+        mut errors,
+        mut error_count,
+        cancelled,
+        aborted,
+        updated_count,
+        deleted_count,
+        moved_count,
+        reflinked_count,
+        deduped_count,
+        linked_count,
+        per_source,
+        copied_files,
+        largest_files,
+        renamed_entries,
+        left_out,
+        progress_seq,
+        ..
+    } = ctx.stats.into_inner().unwrap_or_else(|e| e.into_inner());
+
+    if error_count > errors.len() as u64 {
+        errors.push(BackupError {
+            message: format!("...and {} more errors", error_count - errors.len() as u64),
+            file: None,
+        });
+    }
+
+    let skipped_count = skipped_collision + excluded_filter;
+    if aborted {
+        let _ = app.emit(
+            "backup-aborted",
+            BackupAborted {
+                error_count,
+                max_errors: max_errors.unwrap_or_default() as u64,
+            },
+        );
+    } else if cancelled {
+        let _ = app.emit(
+            "backup-cancelled",
+            BackupCancelled {
+                copied_count,
+                skipped_count,
+            },
+        );
+    }
+
+    let mut message = if aborted && strict {
+        format!("Stopped at the first error after {} files", copied_count)
+    } else if aborted {
+        format!(
+            "Stopped after {} files on reaching the limit of {} errors",
+            copied_count,
+            max_errors.unwrap_or_default()
+        )
+    } else if cancelled {
+        format!("Cancelled after {} files", copied_count)
+    } else if dry_run {
+        let mut message = format!("Dry run: would copy {}", copied_count);
+        if skipped_collision > 0 || excluded_filter == 0 {
+            message.push_str(&format!(", skip {}", skipped_collision));
+        }
+        if excluded_filter > 0 {
+            message.push_str(&format!(", exclude {}", excluded_filter));
+        }
+        message
+    } else if error_count == 0 {
+        if skipped_count > 0 {
+            let mut message = format!("Copied {} files", copied_count);
+            if skipped_collision > 0 {
+                message.push_str(&format!(", skipped {}", skipped_collision));
+            }
+            if excluded_filter > 0 {
+                message.push_str(&format!(", excluded {} by filters", excluded_filter));
+            }
+            message
+        } else {
+            format!("Successfully copied {} files", copied_count)
+        }
     } else {
         format!(
             "Copied {} files with {} errors",
             copied_count,
-            errors.len()
+            error_count
         )
     };
 
+    if reflinked_count > 0 {
+        message.push_str(&format!(" ({} reflinked)", reflinked_count));
+    }
+
+    if updated_count > 0 {
+        let verb = if dry_run { "to update" } else { "updated" };
+        message.push_str(&format!(", {} {}", updated_count, verb));
+    }
+
+    if deduped_count > 0 {
+        message.push_str(&format!(", {} deduplicated", deduped_count));
+    }
+
+    if linked_count > 0 {
+        message.push_str(&format!(", {} linked to the previous backup", linked_count));
+    }
+
+    if let Some(archive_size) = archive_size {
+        message.push_str(&format!(", archive size {}", archive::format_size(archive_size)));
+    }
+
+    if skipped_unreadable > 0 {
+        message.push_str(&format!(", {} unreadable folders skipped", skipped_unreadable));
+    }
+
+    if !left_out.is_empty() {
+        message.push_str(&format!(", {} left out as the target is full", left_out.len()));
+    }
+
+    if deleted_count > 0 {
+        let verb = if dry_run { "delete" } else { "deleted" };
+        message.push_str(&format!(", {} {}", verb, deleted_count));
+    }
+
+    if moved_count > 0 {
+        let verb = if dry_run { "remove" } else { "removed" };
+        message.push_str(&format!(", {} {} from source", verb, moved_count));
+    }
+
+    let average_bytes_per_second = average_rate(copied_bytes, elapsed);
+    if !dry_run {
+        message.push_str(&format!(
+            ", {} in {} ({}/s)",
+            archive::format_size(copied_bytes),
+            format_duration(elapsed),
+            archive::format_size(average_bytes_per_second as u64)
+        ));
+    }
+
+    if write_report && !dry_run && remote.is_none() {
+        let report_dir = if archive_format.is_some() {
+            target.parent().unwrap_or(target)
+        } else {
+            target
+        };
+        let report_path = report_dir.join(report::REPORT_FILENAME);
+        let list = |patterns: &[String]| patterns.join(", ");
+        let extra_target_names: Vec<String> =
+            targets.iter().skip(1).map(|t| t.to_string_lossy().to_string()).collect();
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "none".to_string());
+
+        let report = report::Report {
+            started,
+            finished: chrono::Local::now(),
+            target,
+            message: &message,
+            copied_count,
+            skipped_count: skipped_collision,
+            excluded_count: excluded_filter,
+            copied_bytes,
+            error_count,
+            sources: source_paths
+                .iter()
+                .enumerate()
+                .map(|(i, source)| {
+                    (source.as_str(), per_source.get(i).map_or(0, |tally| tally.copied))
+                })
+                .collect(),
+            settings: vec![
+                ("Extra targets", list(&extra_target_names)),
+                ("Archive", optional(archive.clone())),
+                ("Collision mode", collision_mode.clone()),
+                ("Review files", interactive_filter.to_string()),
+                ("Source folder depth", source_depth.to_string()),
+                ("Blacklist", list(&blacklist)),
+                ("Regex blacklist", list(&regex_blacklist)),
+                ("Absolute blacklist", list(&absolute_blacklist)),
+                ("Whitelist", list(&whitelist)),
+                ("Excluded extensions", list(&exclude_extensions)),
+                ("Respect .gitignore", respect_gitignore.to_string()),
+                ("Respect .m4ssc0pyignore", walk_options.respect_backupignore.to_string()),
+                ("Symlinks", symlink_mode.clone()),
+                ("Sort order", sort_order.map_or("none", SortOrder::name).to_string()),
+                ("Max depth", optional(max_depth.map(|depth| depth.to_string()))),
+                ("Min size", optional(min_size.map(archive::format_size))),
+                ("Max size", optional(max_size.map(archive::format_size))),
+                ("Modified after", optional(modified_after.map(|time| time.to_string()))),
+                ("Modified before", optional(modified_before.map(|time| time.to_string()))),
+                ("Incremental", incremental.to_string()),
+                ("Sync", sync.to_string()),
+                ("Strict", strict.to_string()),
+                ("Verify", verify.to_string()),
+                ("Mirror", mirror.to_string()),
+                ("Fail on foreign files", fail_on_existing_foreign.to_string()),
+                ("Move files", move_files.to_string()),
+                ("Flatten", flatten.to_string()),
+                ("Fill mode", fill_mode.to_string()),
+                ("Name overrides", name_overrides.as_ref().map_or(0, HashMap::len).to_string()),
+                ("Sanitize names", sanitize_names.to_string()),
+                ("Lowercase names", name_rules.lowercase.to_string()),
+                ("Unicode normalization", optional(normalize_unicode.clone())),
+                ("Dedupe", dedupe.to_string()),
+                ("Link to previous backup", optional(link_dest.clone())),
+                ("Deduplicate symlinks", deduplicate_symlinks.to_string()),
+                ("Encrypted", encrypt.is_some().to_string()),
+                ("Atomic", atomic.to_string()),
+                (
+                    "Temp folder",
+                    optional(ctx.temp_dir.as_ref().map(|dir| dir.to_string_lossy().to_string())),
+                ),
+                ("Resume partial copies", resume_partial.to_string()),
+                ("Hash cache", use_hash_cache.to_string()),
+                ("Versioned", versioned.to_string()),
+                ("Preserve timestamps", preserve_timestamps.to_string()),
+                ("Preserve permissions", preserve_permissions.to_string()),
+                ("Preserve creation time", preserve_creation_time.to_string()),
+                ("Preserve extended attributes", preserve_xattr.to_string()),
+                ("Include hidden files", walk_options.include_hidden.to_string()),
+                ("Copy special files", walk_options.copy_special_files.to_string()),
+                ("Buffer size", archive::format_size(ctx.chunked.buffer_size as u64)),
+            ],
+            renamed: &renamed_entries,
+            left_out: &left_out,
+            errors: &errors,
+        };
+
+        if let Err(e) = report.write(&report_path) {
+            error_count += 1;
+            errors.push(BackupError {
+                message: format!("Failed to write report: {}", e),
+                file: Some(report_path.to_string_lossy().to_string()),
+            });
+        }
+    }
+
     let result = BackupComplete {
-        success: errors.is_empty(),
+        success: error_count == 0 && !cancelled,
         copied_count,
         skipped_count,
+        skipped_collision,
+        excluded_filter,
+        skipped_unreadable,
+        left_out_count: left_out.len() as u64,
         message,
+        errors,
+        updated_count,
+        deleted_count,
+        moved_count,
+        reflinked_count,
+        deduped_count,
+        linked_count,
+        copied_files,
+        top_files: largest_files
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((size, path))| (path, size))
+            .collect(),
+        per_source: source_paths
+            .iter()
+            .enumerate()
+            .map(|(i, path)| per_source.get(i).cloned().unwrap_or_default().into_stats(path))
+            .collect(),
+        elapsed_ms: elapsed.as_millis() as u64,
+        average_bytes_per_second,
+        seq: progress_seq + 1,
     };
 
+    tracing::info!(
+        copied = result.copied_count,
+        skipped = result.skipped_count,
+        errors = error_count,
+        elapsed_ms = result.elapsed_ms,
+        "{}",
+        result.message
+    );
     let _ = app.emit("backup-complete", result.clone());
 
     Ok(result)
 }
 
-/// Count total files to copy (for progress calculation)
-fn count_files_multi(source_paths: &[String], glob_set: &GlobSet, respect_gitignore: bool) -> u64 {
-    let mut count: u64 = 0;
+/// Copy the files listed in a manifest from the backup back to their original
+/// locations. The backup is looked up in `backup_path`, or in the target the
+/// manifest was written for if none is given. Existing files are handled by
+/// `collision_mode` like during a backup, and progress is reported through
+/// the usual `backup-progress` events. Manifests of encrypted backups are
+/// refused, since copying their files back would leave encrypted data.
+#[tauri::command]
+async fn restore_backup<R: Runtime>(
+    app: AppHandle<R>,
+    control: State<'_, BackupControl>,
+    manifest_path: String,
+    backup_path: Option<String>,
+    collision_mode: String,
+    dry_run: bool,
+) -> Result<BackupComplete, String> {
+    control.reset();
+    let copy_started = Instant::now();
 
-    for source_path in source_paths {
-        let source = Path::new(source_path);
+    let manifest = Manifest::read(Path::new(&manifest_path))
+        .map_err(|e| format!("Failed to read manifest: {}", e))?;
+    if manifest.encrypted {
+        return Err("Encrypted backups can't be restored".to_string());
+    }
+    let backup_root = PathBuf::from(backup_path.unwrap_or(manifest.target));
+    if !backup_root.is_dir() {
+        return Err(format!(
+            "Backup directory does not exist: {}",
+            backup_root.to_string_lossy()
+        ));
+    }
 
-        if source.is_file() {
-            // Single file
-            if let Some(file_name) = source.file_name() {
-                if !is_blacklisted(Path::new(file_name), glob_set) {
-                    count += 1;
-                }
+    let ctx = BackupContext {
+        app: &app,
+        control: &control,
+        target: &backup_root,
+        collision_mode: &collision_mode,
+        interactive_filter: false,
+        rename_template: None,
+        dry_run,
+        preserve_timestamps: true,
+        preserve_permissions: true,
+        preserve_xattr: false,
+        preserve_creation_time: false,
+        incremental: false,
+        verify: false,
+        mirror: false,
+        atomic: true,
+        move_files: false,
+        prefer_reflink: true,
+        dedupe: false,
+        deduplicate_symlinks: false,
+        resume_partial: false,
+        temp_dir: None,
+        hash_cache: None,
+        link_dest: None,
+        fill_mode: false,
+        encryption: None,
+        max_retries: 3,
+        max_errors: None,
+        chunked: ChunkedCopy::default(),
+        manifest: false,
+        collect_file_list: false,
+        top_files: 0,
+        progress_interval: Duration::from_millis(100),
+        total_count: manifest.files.len() as u64,
+        total_bytes: manifest.files.iter().map(|entry| entry.size).sum(),
+        multiple_targets: false,
+        stats: Mutex::new(BackupStats::default()),
+    };
+
+    for entry in manifest.files {
+        if ctx.is_cancelled() {
+            break;
+        }
+
+        // Refuse entries that would read from outside the backup
+        let relative = Path::new(&entry.path);
+        if relative.is_absolute()
+            || relative
+                .components()
+                .any(|c| matches!(c, std::path::Component::ParentDir))
+        {
+            ctx.record_error("Manifest entry points outside the backup".to_string(), Some(relative));
+            continue;
+        }
+
+        let backup_file = backup_root.join(relative);
+        let original = PathBuf::from(&entry.source);
+
+        if let Some(parent) = original.parent() {
+            if let Err(e) = create_dir(parent, dry_run) {
+                ctx.record_error(format!("Failed to create parent dir: {}", e), Some(parent));
+                continue;
             }
-        } else if source.is_dir() {
-            let mut builder = WalkBuilder::new(source);
-            builder
-                .hidden(false)
-                .git_ignore(respect_gitignore)
-                .git_global(false)
-                .git_exclude(respect_gitignore);
-            let walker = builder.build();
+        }
 
-            for entry in walker {
-                if let Ok(dir_entry) = entry {
-                    let path = dir_entry.path();
+        let symlink = fs::symlink_metadata(&backup_file).is_ok_and(|m| m.file_type().is_symlink());
+        copy_job(
+            &ctx,
+            CopyJob {
+                source: backup_file,
+                dest: original,
+                display_name: entry.path,
+                symlink,
+                source_index: 0,
+                dir: None,
+            },
+        );
+    }
 
-                    if path.is_file() {
-                        if let Ok(relative) = path.strip_prefix(source) {
-                            if !is_blacklisted(relative, glob_set) {
-                                count += 1;
-                            }
-                        }
-                    }
+    ctx.flush_progress();
+
+    let elapsed = copy_started.elapsed();
+    let BackupStats {
+        copied_count,
+        copied_bytes,
+        skipped_count,
+        mut errors,
+        error_count,
+        cancelled,
+        updated_count,
+        reflinked_count,
+        per_source,
+        progress_seq,
+        ..
+    } = ctx.stats.into_inner().unwrap_or_else(|e| e.into_inner());
+
+    if error_count > errors.len() as u64 {
+        errors.push(BackupError {
+            message: format!("...and {} more errors", error_count - errors.len() as u64),
+            file: None,
+        });
+    }
+
+    let message = if cancelled {
+        format!("Restore cancelled after {} files", copied_count)
+    } else if dry_run {
+        format!("Dry run: would restore {}, skip {}", copied_count, skipped_count)
+    } else if error_count == 0 {
+        format!("Restored {} files, skipped {}", copied_count, skipped_count)
+    } else {
+        format!("Restored {} files with {} errors", copied_count, error_count)
+    };
+
+    let result = BackupComplete {
+        success: error_count == 0 && !cancelled,
+        copied_count,
+        skipped_count,
+        skipped_collision: skipped_count,
+        excluded_filter: 0,
+        skipped_unreadable: 0,
+        left_out_count: 0,
+        message,
+        errors,
+        updated_count,
+        deleted_count: 0,
+        moved_count: 0,
+        reflinked_count,
+        deduped_count: 0,
+        linked_count: 0,
+        copied_files: Vec::new(),
+        top_files: Vec::new(),
+        // Everything is restored from the one backup folder
+        per_source: vec![per_source
+            .into_iter()
+            .next()
+            .unwrap_or_default()
+            .into_stats(&backup_root.to_string_lossy())],
+        elapsed_ms: elapsed.as_millis() as u64,
+        average_bytes_per_second: average_rate(copied_bytes, elapsed),
+        seq: progress_seq + 1,
+    };
+
+    tracing::info!(manifest = %manifest_path, "{}", result.message);
+    let _ = app.emit("restore-complete", result.clone());
+
+    Ok(result)
+}
+
+/// Files that differ between two directories, as relative paths with `/`
+/// separators
+#[derive(Clone, Serialize)]
+struct DirectoryDiff {
+    /// Only in the source
+    added: Vec<String>,
+    /// Only in the target
+    removed: Vec<String>,
+    /// In both, but with a different size or modification time
+    modified: Vec<String>,
+    /// Problems encountered while walking either directory
+    errors: Vec<String>,
+}
+
+/// Compare `source_path` with `target_path` without copying anything, for
+/// example to see what a mirror backup would change. Both are walked with
+/// the same filters, so files a backup would leave out appear in no list.
+/// Like incremental mode, files count as modified when their size or
+/// modification time differs.
+#[tauri::command]
+async fn diff_directories(
+    source_path: String,
+    target_path: String,
+    blacklist: Vec<String>,
+    whitelist: Vec<String>,
+    respect_gitignore: bool,
+    case_insensitive: Option<bool>,
+) -> Result<DirectoryDiff, String> {
+    let source = Path::new(&source_path);
+    let target = Path::new(&target_path);
+    for dir in [source, target] {
+        if !dir.is_dir() {
+            return Err(format!("Directory does not exist: {}", dir.to_string_lossy()));
+        }
+    }
+
+    let case_insensitive = case_insensitive.unwrap_or(cfg!(windows));
+    let filter = FileFilter::new(&blacklist, &whitelist, case_insensitive);
+    let mut errors = filter.warnings.clone();
+    // Each walk stays out of the other directory in case one contains it
+    let options_skipping = |other: &Path| WalkOptions {
+        include_hidden: true,
+        respect_gitignore,
+        respect_backupignore: true,
+        symlink_mode: SymlinkMode::Copy,
+        max_depth: None,
+        skip_paths: other.canonicalize().into_iter().collect(),
+        copy_special_files: false,
+    };
+    let source_files = list_files(source, &filter, &options_skipping(target), &mut errors);
+    let target_files = list_files(target, &filter, &options_skipping(source), &mut errors);
+
+    let mut added = Vec::new();
+    let mut modified = Vec::new();
+    for (name, path) in &source_files {
+        match target_files.get(name) {
+            None => added.push(name.clone()),
+            Some(dest) if !is_unchanged(path, dest) => modified.push(name.clone()),
+            Some(_) => {}
+        }
+    }
+    let removed = target_files
+        .into_keys()
+        .filter(|name| !source_files.contains_key(name))
+        .collect();
+
+    Ok(DirectoryDiff {
+        added,
+        removed,
+        modified,
+        errors,
+    })
+}
+
+/// Every file and symlink below `root` that passes `filter`, keyed by its
+/// relative path with `/` separators
+fn list_files(
+    root: &Path,
+    filter: &FileFilter,
+    options: &WalkOptions,
+    errors: &mut Vec<String>,
+) -> BTreeMap<String, PathBuf> {
+    let mut files = BTreeMap::new();
+    let _ = walk_source(root, filter, options, &mut |entry| {
+        match entry {
+            PlanEntry::File {
+                source, relative, ..
+            }
+            | PlanEntry::Symlink { source, relative } => {
+                files.insert(archive::entry_name(&relative), source);
+            }
+            PlanEntry::Unreadable { path } => errors.push(format!(
+                "Skipped unreadable directory {}",
+                path.to_string_lossy()
+            )),
+            PlanEntry::Error(message) => errors.push(message),
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    });
+    files
+}
+
+/// Time `estimate_backup` spends on the sources unless told otherwise
+const DEFAULT_ESTIMATE_BUDGET: Duration = Duration::from_secs(2);
+
+/// Copy speed `estimate_backup` assumes unless told otherwise, 100 MiB/s
+const DEFAULT_ASSUMED_THROUGHPUT: u64 = 100 * 1024 * 1024;
+
+/// Rough size of a backup, from `estimate_backup`
+#[derive(Clone, Serialize)]
+struct BackupEstimate {
+    total_count: u64,
+    total_bytes: u64,
+    /// How long copying `total_bytes` takes at the assumed throughput
+    estimated_seconds: u64,
+    /// False if the sources could be counted completely within the time
+    /// budget, so the totals are exact
+    approximate: bool,
+}
+
+/// Guess how many files and bytes a backup of the sources would copy, and
+/// how long it would take at `assumed_bytes_per_second` (100 MiB/s by
+/// default), within about `time_budget_ms` (2 seconds by default). Sources
+/// that can be counted in half the budget give exact totals. Otherwise the
+/// rest of the budget goes to sampling, whose result is marked approximate
+/// and doesn't consult `.gitignore` files.
+#[tauri::command]
+async fn estimate_backup(
+    source_paths: Vec<String>,
+    blacklist: Vec<String>,
+    whitelist: Vec<String>,
+    respect_gitignore: bool,
+    case_insensitive: Option<bool>,
+    time_budget_ms: Option<u64>,
+    assumed_bytes_per_second: Option<u64>,
+) -> Result<BackupEstimate, String> {
+    for source_path in &source_paths {
+        if !Path::new(source_path).exists() {
+            return Err(format!("Source path does not exist: {}", source_path));
+        }
+    }
+
+    let case_insensitive = case_insensitive.unwrap_or(cfg!(windows));
+    let filter = FileFilter::new(&blacklist, &whitelist, case_insensitive);
+    let options = WalkOptions {
+        include_hidden: true,
+        respect_gitignore,
+        respect_backupignore: true,
+        symlink_mode: SymlinkMode::Copy,
+        max_depth: None,
+        skip_paths: Vec::new(),
+        copy_special_files: false,
+    };
+
+    let budget = time_budget_ms.map_or(DEFAULT_ESTIMATE_BUDGET, Duration::from_millis);
+    let started = Instant::now();
+    let sources: Vec<Source> =
+        source_paths.iter().map(|path| Source { path, filter: &filter }).collect();
+    let mut complete = true;
+    let (mut total_count, mut total_bytes) =
+        walk_sources(&sources, &options, &mut |_, _| {}, &mut |_| {
+            if started.elapsed() < budget / 2 {
+                ControlFlow::Continue(())
+            } else {
+                complete = false;
+                ControlFlow::Break(())
+            }
+        });
+    if !complete {
+        (total_count, total_bytes) = sample_tree_sizes(&source_paths, &filter, started + budget);
+    }
+
+    let throughput = assumed_bytes_per_second.unwrap_or(DEFAULT_ASSUMED_THROUGHPUT).max(1);
+    Ok(BackupEstimate {
+        total_count,
+        total_bytes,
+        estimated_seconds: total_bytes.div_ceil(throughput),
+        approximate: !complete,
+    })
+}
+
+/// Estimate the files and bytes below `sources` from random descents, each
+/// following one random subdirectory per level and weighting what it finds
+/// by the number of choices made on the way there (Knuth's estimator). The
+/// descents are repeated until `deadline`, shared out evenly between the
+/// sources, and averaged.
+fn sample_tree_sizes(sources: &[String], filter: &FileFilter, deadline: Instant) -> (u64, u64) {
+    let mut random = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(1, |time| time.as_nanos() as u64 | 1);
+    let (mut count, mut bytes) = (0.0, 0.0);
+
+    for (i, source) in sources.iter().enumerate() {
+        let source = Path::new(source);
+        if !source.is_dir() {
+            count += 1.0;
+            bytes += fs::metadata(source).map_or(0, |m| m.len()) as f64;
+            continue;
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let source_deadline = Instant::now() + remaining / (sources.len() - i) as u32;
+        let (mut sum_count, mut sum_bytes, mut descents) = (0.0, 0.0, 0);
+        // At least one descent, however short the budget
+        while descents == 0 || Instant::now() < source_deadline {
+            let (found_count, found_bytes) = random_descent(source, filter, &mut random);
+            sum_count += found_count;
+            sum_bytes += found_bytes;
+            descents += 1;
+        }
+        count += sum_count / descents as f64;
+        bytes += sum_bytes / descents as f64;
+    }
+
+    (count.round() as u64, bytes.round() as u64)
+}
+
+/// One descent of `sample_tree_sizes` from `root` to a random leaf folder,
+/// returning the weighted file count and bytes
+fn random_descent(root: &Path, filter: &FileFilter, random: &mut u64) -> (f64, f64) {
+    let (mut count, mut bytes, mut weight) = (0.0, 0.0, 1.0);
+    let mut dir = root.to_path_buf();
+
+    loop {
+        let Ok(entries) = fs::read_dir(&dir) else { break };
+        let mut subdirs = Vec::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(file_type) = entry.file_type() else { continue };
+            if file_type.is_dir() {
+                subdirs.push(path);
+            } else if filter.accepts_file(path.strip_prefix(root).unwrap_or(&path)) {
+                count += weight;
+                bytes += weight * entry.metadata().map_or(0, |m| m.len()) as f64;
+            }
+        }
+        if subdirs.is_empty() {
+            break;
+        }
+
+        // xorshift64, plenty for picking folders
+        *random ^= *random << 13;
+        *random ^= *random >> 7;
+        *random ^= *random << 17;
+        weight *= subdirs.len() as f64;
+        dir = subdirs.swap_remove(*random as usize % subdirs.len());
+    }
+
+    (count, bytes)
+}
+
+/// How a blacklist or whitelist pattern would be matched
+#[derive(Clone, Serialize)]
+struct PatternCheck {
+    pattern: String,
+    /// `"glob"`, `"literal"` if it's matched as a plain name instead, or
+    /// `"ignored"` if it can't be used at all
+    status: &'static str,
+    /// Why the pattern isn't a valid glob
+    error: Option<String>,
+}
+
+/// Check patterns the way a backup would compile them, without touching the
+/// file system, so the frontend can flag mistakes while they're typed. A
+/// leading `!` is skipped and a leading `/` anchors, like in the blacklist.
+#[tauri::command]
+fn validate_patterns(patterns: Vec<String>) -> Vec<PatternCheck> {
+    patterns
+        .into_iter()
+        .map(|pattern| {
+            let glob = pattern.strip_prefix('!').unwrap_or(&pattern);
+            let (compiled, error) = match glob.strip_prefix('/') {
+                Some(glob) => compile_pattern(glob, false, true),
+                None => compile_pattern(glob, false, false),
+            };
+            let status = match (&compiled, &error) {
+                (_, None) => "glob",
+                (Some(_), Some(_)) => "literal",
+                (None, Some(_)) => "ignored",
+            };
+            PatternCheck {
+                pattern,
+                status,
+                error,
+            }
+        })
+        .collect()
+}
+
+/// Request cancellation of the backup currently in progress
+#[tauri::command]
+fn cancel_backup(control: State<'_, BackupControl>) {
+    control.cancelled.store(true, Ordering::SeqCst);
+}
+
+/// Answer the `backup-conflict` event the current backup is waiting on with
+/// `overwrite`, `skip`, `rename`, `overwrite-all` or `skip-all`
+#[tauri::command]
+fn resolve_conflict(control: State<'_, BackupControl>, decision: String) -> Result<(), String> {
+    let decision = ConflictDecision::parse(&decision)
+        .ok_or_else(|| format!("Unknown conflict decision: {}", decision))?;
+    control
+        .decisions
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .send(decision)
+        .map_err(|e| e.to_string())
+}
+
+/// Approve the file the current backup is waiting on in review mode. With
+/// `rest` set, every later file is copied without asking.
+#[tauri::command]
+fn approve_file(control: State<'_, BackupControl>, rest: Option<bool>) -> Result<(), String> {
+    control.send_review(ReviewDecision {
+        approved: true,
+        rest: rest.unwrap_or(false),
+    })
+}
+
+/// Reject the file the current backup is waiting on in review mode, leaving
+/// it out like a filtered file. With `rest` set, every later file is left
+/// out too.
+#[tauri::command]
+fn reject_file(control: State<'_, BackupControl>, rest: Option<bool>) -> Result<(), String> {
+    control.send_review(ReviewDecision {
+        approved: false,
+        rest: rest.unwrap_or(false),
+    })
+}
+
+/// Count total files and bytes to copy (for progress calculation).
+/// `on_progress` receives the running totals every `COUNTING_INTERVAL` files.
+///
+/// Only the file system is read, with each source's filter and the walk
+/// options applied exactly as the copy applies them, so the result can be
+/// checked without a running app.
+pub(crate) fn count_files_multi(
+    sources: &[Source],
+    options: &WalkOptions,
+    on_progress: &mut dyn FnMut(u64, u64),
+) -> (u64, u64) {
+    walk_sources(sources, options, on_progress, &mut |_| ControlFlow::Continue(()))
+}
+
+/// Walk the sources one after another, handing every entry to `visit`, and
+/// return the total files and bytes found. `on_progress` receives the
+/// running totals every `COUNTING_INTERVAL` files. Returning
+/// `ControlFlow::Break` from `visit` stops before the next entry.
+fn walk_sources(
+    sources: &[Source],
+    options: &WalkOptions,
+    on_progress: &mut dyn FnMut(u64, u64),
+    visit: &mut dyn FnMut(PlanEntry) -> ControlFlow<()>,
+) -> (u64, u64) {
+    let mut count: u64 = 0;
+    let mut bytes: u64 = 0;
+
+    for source in sources {
+        let flow = walk_source(Path::new(source.path), source.filter, options, &mut |entry| {
+            if let Some(size) = entry.file_size() {
+                count += 1;
+                bytes += size;
+                if count.is_multiple_of(COUNTING_INTERVAL) {
+                    on_progress(count, bytes);
                 }
             }
+            visit(entry)
+        });
+        if flow.is_break() {
+            break;
         }
     }
 
-    count
+    (count, bytes)
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -382,7 +5588,29 @@ pub fn run() {
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_opener::init())
-        .invoke_handler(tauri::generate_handler![backup_directory])
+        .manage(BackupControl::default())
+        .setup(|app| {
+            // The backups run just as well without a log
+            let log = app.path().app_log_dir().map_err(|e| e.to_string());
+            match log.and_then(|dir| BackupLog::init(&dir)) {
+                Ok(log) => {
+                    app.manage(log);
+                }
+                Err(e) => eprintln!("Logging to a file is disabled: {}", e),
+            }
+            Ok(())
+        })
+        .invoke_handler(tauri::generate_handler![
+            backup_directory,
+            restore_backup,
+            cancel_backup,
+            resolve_conflict,
+            approve_file,
+            reject_file,
+            diff_directories,
+            validate_patterns,
+            estimate_backup
+        ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
@@ -0,0 +1,57 @@
+//! A durable log of every backup, written next to the live events so failed
+//! runs can be looked into afterwards. A new file is started every day and
+//! only the most recent ones are kept.
+
+use std::path::Path;
+use std::str::FromStr;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, Registry};
+
+/// Number of daily log files kept before the oldest is deleted
+const MAX_LOG_FILES: usize = 14;
+
+/// Level used when a backup doesn't ask for one
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::INFO;
+
+/// The installed logger, managed as app state so each backup can pick how
+/// much it records
+pub(crate) struct BackupLog {
+    level: reload::Handle<LevelFilter, Registry>,
+}
+
+impl BackupLog {
+    /// Start logging into `backup.<date>.log` files inside `dir`
+    pub fn init(dir: &Path) -> Result<Self, String> {
+        let appender = RollingFileAppender::builder()
+            .rotation(Rotation::DAILY)
+            .filename_prefix("backup")
+            .filename_suffix("log")
+            .max_log_files(MAX_LOG_FILES)
+            .build(dir)
+            .map_err(|e| format!("Failed to open log folder {}: {}", dir.display(), e))?;
+
+        let (filter, level) = reload::Layer::new(DEFAULT_LEVEL);
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(fmt::layer().with_writer(appender).with_ansi(false))
+            .try_init()
+            .map_err(|e| e.to_string())?;
+
+        Ok(BackupLog { level })
+    }
+
+    /// Record events up to `level`: `error`, `warn`, `info`, `debug` or
+    /// `trace`, or the default `info` when none is given
+    pub fn set_level(&self, level: Option<&str>) -> Result<(), String> {
+        let level = match level {
+            Some(level) => {
+                LevelFilter::from_str(level).map_err(|_| format!("Unknown log level: {}", level))?
+            }
+            None => DEFAULT_LEVEL,
+        };
+        self.level.modify(|filter| *filter = level).map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,60 @@
+//! JSON manifest describing the files written by a backup, so the backup can
+//! be verified later.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct ManifestEntry {
+    /// Destination path relative to the backup target
+    pub path: String,
+    /// Absolute path the file was copied from
+    pub source: String,
+    pub size: u64,
+    /// Source modification time in seconds since the Unix epoch
+    pub modified: Option<u64>,
+    /// BLAKE3 hash of the contents, when verification computed one
+    pub checksum: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Manifest {
+    /// Creation time in seconds since the Unix epoch
+    pub created: u64,
+    pub target: String,
+    /// Whether the files were encrypted, which means they can't be restored
+    /// by copying them back
+    #[serde(default)]
+    pub encrypted: bool,
+    pub files: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn new(target: &Path, files: Vec<ManifestEntry>, encrypted: bool) -> Self {
+        Manifest {
+            created: unix_seconds(SystemTime::now()).unwrap_or(0),
+            target: target.to_string_lossy().to_string(),
+            encrypted,
+            files,
+        }
+    }
+
+    pub fn read(path: &Path) -> std::io::Result<Self> {
+        let reader = BufReader::new(fs::File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        let writer = BufWriter::new(fs::File::create(path)?);
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+}
+
+/// Convert a timestamp to whole seconds since the Unix epoch
+pub(crate) fn unix_seconds(time: SystemTime) -> Option<u64> {
+    time.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs())
+}
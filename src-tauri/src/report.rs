@@ -0,0 +1,111 @@
+//! Human-readable summary of a backup run, written next to the backup.
+
+use chrono::{DateTime, Local};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use crate::archive::format_size;
+use crate::BackupError;
+
+/// File name of the report inside the target directory
+pub(crate) const REPORT_FILENAME: &str = "backup_report.txt";
+
+pub(crate) struct Report<'a> {
+    pub started: DateTime<Local>,
+    pub finished: DateTime<Local>,
+    pub target: &'a Path,
+    pub message: &'a str,
+    pub copied_count: u64,
+    pub skipped_count: u64,
+    pub excluded_count: u64,
+    pub copied_bytes: u64,
+    pub error_count: u64,
+    /// Files copied from each source root
+    pub sources: Vec<(&'a str, u64)>,
+    /// Settings of the run as name/value pairs
+    pub settings: Vec<(&'static str, String)>,
+    /// Original and new relative path of every entry whose name was changed
+    pub renamed: &'a [(String, String)],
+    /// Files that didn't fit into the target in fill mode
+    pub left_out: &'a [String],
+    pub errors: &'a [BackupError],
+}
+
+impl Report<'_> {
+    fn render(&self) -> String {
+        let time = |time: &DateTime<Local>| time.format("%Y-%m-%d %H:%M:%S").to_string();
+        let duration = (self.finished - self.started).num_milliseconds() as f64 / 1000.0;
+
+        // Writing to a String can't fail
+        let mut out = String::new();
+        let _ = writeln!(out, "m4ssc0py backup report");
+        let _ = writeln!(out, "======================");
+        let _ = writeln!(out);
+        let _ = writeln!(out, "Started:  {}", time(&self.started));
+        let _ = writeln!(out, "Finished: {}", time(&self.finished));
+        let _ = writeln!(out, "Duration: {:.1} s", duration);
+        let _ = writeln!(out, "Target:   {}", self.target.to_string_lossy());
+        let _ = writeln!(out, "Result:   {}", self.message);
+        let _ = writeln!(out);
+        let _ = writeln!(out, "Files copied:   {}", self.copied_count);
+        let _ = writeln!(out, "Files skipped:  {}", self.skipped_count);
+        let _ = writeln!(out, "Files excluded: {}", self.excluded_count);
+        let _ = writeln!(out, "Bytes copied:   {}", format_size(self.copied_bytes));
+        let _ = writeln!(out, "Errors:         {}", self.error_count);
+
+        let _ = writeln!(out);
+        let _ = writeln!(out, "Sources");
+        let _ = writeln!(out, "-------");
+        for (source, count) in &self.sources {
+            let _ = writeln!(out, "{}: {} files", source, count);
+        }
+
+        let _ = writeln!(out);
+        let _ = writeln!(out, "Settings");
+        let _ = writeln!(out, "--------");
+        for (name, value) in &self.settings {
+            let _ = writeln!(out, "{}: {}", name, value);
+        }
+
+        if !self.renamed.is_empty() {
+            let _ = writeln!(out);
+            let _ = writeln!(out, "Renamed");
+            let _ = writeln!(out, "-------");
+            for (from, to) in self.renamed {
+                let _ = writeln!(out, "{} -> {}", from, to);
+            }
+        }
+
+        if !self.left_out.is_empty() {
+            let _ = writeln!(out);
+            let _ = writeln!(out, "Left out");
+            let _ = writeln!(out, "--------");
+            for file in self.left_out {
+                let _ = writeln!(out, "{}", file);
+            }
+        }
+
+        if !self.errors.is_empty() {
+            let _ = writeln!(out);
+            let _ = writeln!(out, "Errors");
+            let _ = writeln!(out, "------");
+            for error in self.errors {
+                match &error.file {
+                    Some(file) => {
+                        let _ = writeln!(out, "{}: {}", file, error.message);
+                    }
+                    None => {
+                        let _ = writeln!(out, "{}", error.message);
+                    }
+                }
+            }
+        }
+
+        out
+    }
+
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        fs::write(path, self.render())
+    }
+}
@@ -0,0 +1,266 @@
+//! Uploading a backup to a remote server over SFTP instead of a local folder.
+
+use ssh2::{CheckResult, FileStat, KnownHostFileKind, Session, Sftp};
+use std::fs;
+use std::io;
+use std::net::TcpStream;
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::TEMP_SUFFIX;
+
+/// Prefix of target paths that name a remote folder
+const SCHEME: &str = "sftp://";
+
+const DEFAULT_PORT: u16 = 22;
+
+/// A remote folder given as `sftp://user@host[:port]/path`
+pub(crate) struct RemoteTarget {
+    pub user: String,
+    pub host: String,
+    pub port: u16,
+    /// Absolute folder on the server
+    pub path: PathBuf,
+}
+
+impl RemoteTarget {
+    /// Parse a target path, `None` if it isn't an SFTP URL at all
+    pub fn parse(target: &str) -> Option<Result<Self, String>> {
+        let rest = target.strip_prefix(SCHEME)?;
+        let invalid = |reason: &str| Err(format!("Invalid SFTP target {}: {}", target, reason));
+
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => return Some(invalid("missing a folder path")),
+        };
+        let Some((user, address)) = authority.rsplit_once('@') else {
+            return Some(invalid("missing a user name"));
+        };
+        let (host, port) = match address.rsplit_once(':') {
+            Some((host, port)) => match port.parse() {
+                Ok(port) => (host, port),
+                Err(_) => return Some(invalid("bad port")),
+            },
+            None => (address, DEFAULT_PORT),
+        };
+        if user.is_empty() || host.is_empty() {
+            return Some(invalid("missing a user name or host"));
+        }
+
+        Some(Ok(RemoteTarget {
+            user: user.to_string(),
+            host: host.to_string(),
+            port,
+            path: PathBuf::from(path),
+        }))
+    }
+}
+
+/// How to log in to the server. Without a key or password the running SSH
+/// agent is asked.
+pub(crate) struct Credentials<'a> {
+    pub password: Option<&'a str>,
+    /// Private key file; `password` then unlocks the key
+    pub key_path: Option<&'a Path>,
+}
+
+/// An open SFTP connection
+pub(crate) struct SftpSession {
+    // Kept alive for as long as the SFTP channel is used
+    _session: Session,
+    sftp: Sftp,
+}
+
+impl SftpSession {
+    /// Connect and log in. The server's host key has to be listed in
+    /// `~/.ssh/known_hosts`, so a spoofed server is refused.
+    pub fn connect(target: &RemoteTarget, credentials: &Credentials) -> Result<Self, String> {
+        let address = format!("{}:{}", target.host, target.port);
+        let stream = TcpStream::connect(&address)
+            .map_err(|e| format!("Failed to connect to {}: {}", address, e))?;
+
+        let mut session = Session::new().map_err(|e| e.to_string())?;
+        session.set_tcp_stream(stream);
+        session
+            .handshake()
+            .map_err(|e| format!("SSH handshake with {} failed: {}", address, e))?;
+        verify_host_key(&session, target)?;
+
+        let auth = match (credentials.key_path, credentials.password) {
+            (Some(key_path), passphrase) => {
+                session.userauth_pubkey_file(&target.user, None, key_path, passphrase)
+            }
+            (None, Some(password)) => session.userauth_password(&target.user, password),
+            (None, None) => session.userauth_agent(&target.user),
+        };
+        auth.map_err(|e| format!("SSH login as {} failed: {}", target.user, e))?;
+
+        let sftp = session
+            .sftp()
+            .map_err(|e| format!("Failed to start SFTP on {}: {}", address, e))?;
+        Ok(SftpSession {
+            _session: session,
+            sftp,
+        })
+    }
+
+    /// Metadata of the entry at `path` without following links, `None` if
+    /// there is nothing there
+    pub fn stat(&self, path: &Path) -> Option<FileStat> {
+        self.sftp.lstat(path).ok()
+    }
+
+    /// Create `path` and any missing parents
+    pub fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        for current in remote_ancestors(path) {
+            let current = Path::new(&current);
+            if self.sftp.stat(current).is_err() {
+                self.sftp.mkdir(current, 0o755)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Upload `source` to `dest`, replacing an existing file, and return the
+    /// bytes written. The modified time and permissions are carried over when
+    /// asked to. The data goes to a temporary file next to `dest` first, which
+    /// is renamed into place once complete, so an interrupted upload leaves
+    /// the old file intact.
+    pub fn upload(
+        &self,
+        source: &Path,
+        dest: &Path,
+        preserve_timestamps: bool,
+        preserve_permissions: bool,
+    ) -> io::Result<u64> {
+        let temp = PathBuf::from(format!("{}{}", dest.to_string_lossy(), TEMP_SUFFIX));
+        let result = self
+            .write_file(source, &temp, preserve_timestamps, preserve_permissions)
+            .and_then(|written| self.replace(&temp, dest).map(|_| written));
+        if result.is_err() {
+            let _ = self.sftp.unlink(&temp);
+        }
+        result
+    }
+
+    /// Rename `from` to `to`, replacing an existing file
+    fn replace(&self, from: &Path, to: &Path) -> io::Result<()> {
+        if self.sftp.rename(from, to, None).is_ok() {
+            return Ok(());
+        }
+        // Servers speaking SFTP version 3 refuse to rename over a file
+        if self.stat(to).is_some() {
+            self.sftp.unlink(to)?;
+        }
+        self.sftp.rename(from, to, None)?;
+        Ok(())
+    }
+
+    /// Write `source` to `dest` and set its metadata, see `upload`
+    fn write_file(
+        &self,
+        source: &Path,
+        dest: &Path,
+        preserve_timestamps: bool,
+        preserve_permissions: bool,
+    ) -> io::Result<u64> {
+        let mut reader = fs::File::open(source)?;
+        let metadata = reader.metadata()?;
+        let mut writer = self.sftp.create(dest)?;
+        let written = io::copy(&mut reader, &mut writer)?;
+        drop(writer);
+
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|since| since.as_secs())
+            .filter(|_| preserve_timestamps);
+        let perm = permission_bits(&metadata).filter(|_| preserve_permissions);
+        if mtime.is_some() || perm.is_some() {
+            self.sftp.setstat(
+                dest,
+                FileStat {
+                    size: None,
+                    uid: None,
+                    gid: None,
+                    perm,
+                    atime: mtime,
+                    mtime,
+                },
+            )?;
+        }
+
+        Ok(written)
+    }
+
+    /// Create a symbolic link at `dest` pointing to `link`, replacing an
+    /// existing entry
+    pub fn symlink(&self, link: &Path, dest: &Path) -> io::Result<()> {
+        if self.stat(dest).is_some() {
+            self.sftp.unlink(dest)?;
+        }
+        self.sftp.symlink(dest, link)?;
+        Ok(())
+    }
+}
+
+/// `path` and each of its parents, starting from the top, joined with the
+/// `/` the server expects whatever the local separator is
+pub(crate) fn remote_ancestors(path: &Path) -> Vec<String> {
+    let mut current = String::new();
+    let mut ancestors = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::RootDir => current.push('/'),
+            component => {
+                if !current.is_empty() && !current.ends_with('/') {
+                    current.push('/');
+                }
+                current.push_str(&component.as_os_str().to_string_lossy());
+            }
+        }
+        ancestors.push(current.clone());
+    }
+    ancestors
+}
+
+#[cfg(unix)]
+fn permission_bits(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn permission_bits(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+/// Check the server's host key against the user's known_hosts file
+fn verify_host_key(session: &Session, target: &RemoteTarget) -> Result<(), String> {
+    let home = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .ok_or("Can't find the home folder to read known_hosts from")?;
+    let known_hosts_path = Path::new(&home).join(".ssh").join("known_hosts");
+
+    let mut known_hosts = session.known_hosts().map_err(|e| e.to_string())?;
+    known_hosts
+        .read_file(&known_hosts_path, KnownHostFileKind::OpenSSH)
+        .map_err(|e| format!("Failed to read {}: {}", known_hosts_path.display(), e))?;
+    let (key, _) = session
+        .host_key()
+        .ok_or_else(|| format!("{} sent no host key", target.host))?;
+
+    match known_hosts.check_port(&target.host, target.port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::Mismatch => Err(format!(
+            "Host key of {} doesn't match known_hosts, refusing to connect",
+            target.host
+        )),
+        CheckResult::NotFound => Err(format!(
+            "{} isn't in known_hosts; connect with ssh once to add it",
+            target.host
+        )),
+        CheckResult::Failure => Err(format!("Failed to check the host key of {}", target.host)),
+    }
+}
@@ -0,0 +1,1105 @@
+//! Whole backups run against a mock app, on files in temporary folders, and
+//! checks of the helpers they are built on.
+
+use super::*;
+use std::sync::atomic::AtomicUsize;
+use tauri::test::{mock_app, MockRuntime};
+use tauri::{App, Listener};
+use tempfile::TempDir;
+
+/// The settings a test changes. Everything else is sent the way the
+/// frontend sends it.
+struct Backup {
+    sources: Vec<PathBuf>,
+    target: PathBuf,
+    blacklist: Vec<String>,
+    respect_gitignore: bool,
+    include_source_dir: bool,
+    collision_mode: &'static str,
+    concurrency: Option<usize>,
+    incremental: bool,
+    verify: bool,
+    mirror: bool,
+    atomic: bool,
+    prefer_reflink: Option<bool>,
+    max_retries: Option<u32>,
+    dedupe: bool,
+    encrypt: Option<String>,
+    resume_partial: bool,
+    sort_order: Option<&'static str>,
+    link_dest: Option<PathBuf>,
+    fail_on_existing_foreign: bool,
+    strict: bool,
+    fill_mode: bool,
+    name_overrides: Option<HashMap<String, String>>,
+}
+
+impl Backup {
+    fn new(sources: &[&Path], target: &Path) -> Self {
+        Backup {
+            sources: sources.iter().map(|source| source.to_path_buf()).collect(),
+            target: target.to_path_buf(),
+            blacklist: Vec::new(),
+            respect_gitignore: false,
+            include_source_dir: false,
+            collision_mode: "overwrite",
+            concurrency: None,
+            incremental: false,
+            verify: false,
+            mirror: false,
+            atomic: true,
+            prefer_reflink: None,
+            max_retries: None,
+            dedupe: false,
+            encrypt: None,
+            resume_partial: false,
+            sort_order: None,
+            link_dest: None,
+            fail_on_existing_foreign: false,
+            strict: false,
+            fill_mode: false,
+            name_overrides: None,
+        }
+    }
+
+    /// Copy the files one at a time, in name order
+    fn in_order(mut self) -> Self {
+        self.concurrency = Some(1);
+        self.sort_order = Some("name");
+        self
+    }
+
+    fn run(&self, app: &App<MockRuntime>) -> Result<BackupComplete, String> {
+        let path = |path: &Path| path.to_string_lossy().to_string();
+        tauri::async_runtime::block_on(backup_directory(
+            app.handle().clone(),
+            app.state::<BackupControl>(),
+            self.sources.iter().map(|source| path(source)).collect(),
+            path(&self.target),
+            self.blacklist.clone(),
+            Vec::new(),
+            self.respect_gitignore,
+            self.include_source_dir,
+            self.collision_mode.to_string(),
+            false,
+            self.concurrency,
+            true,
+            self.incremental,
+            self.verify,
+            self.mirror,
+            None,
+            false,
+            None,
+            "follow".to_string(),
+            self.atomic,
+            None,
+            false,
+            None,
+            None,
+            self.prefer_reflink,
+            None,
+            true,
+            None,
+            self.max_retries,
+            None,
+            None,
+            false,
+            None,
+            None,
+            self.dedupe,
+            None,
+            Vec::new(),
+            false,
+            None,
+            false,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            self.encrypt.clone(),
+            false,
+            None,
+            false,
+            None,
+            false,
+            None,
+            self.resume_partial,
+            None,
+            None,
+            None,
+            self.fail_on_existing_foreign,
+            false,
+            None,
+            None,
+            self.fill_mode,
+            None,
+            Vec::new(),
+            false,
+            self.link_dest.as_deref().map(path),
+            self.sort_order.map(str::to_string),
+            false,
+            false,
+            false,
+            Vec::new(),
+            self.strict,
+            self.name_overrides.clone(),
+        ))
+    }
+}
+
+/// A mock app managing the state the commands expect
+fn app() -> App<MockRuntime> {
+    let app = mock_app();
+    app.manage(BackupControl::default());
+    app
+}
+
+/// Make every copy of a file named `name` fail with `kind` on the attempts
+/// `fails` accepts
+fn fail_file(
+    app: &App<MockRuntime>,
+    name: &'static str,
+    kind: std::io::ErrorKind,
+    fails: fn(u32) -> bool,
+) {
+    let hook: FaultHook = Arc::new(move |source: &Path, attempt: u32| {
+        (source.file_name() == Some(OsStr::new(name)) && fails(attempt)).then_some(kind)
+    });
+    *app.state::<BackupControl>().faults.lock().unwrap() = Some(hook);
+}
+
+/// Count the events of one kind the app sends
+fn count_events(app: &App<MockRuntime>, event: &str) -> Arc<AtomicUsize> {
+    let count = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&count);
+    app.listen_any(event.to_string(), move |_| {
+        counter.fetch_add(1, Ordering::SeqCst);
+    });
+    count
+}
+
+/// A folder holding the given files, with their parent folders
+fn tree(files: &[(&str, &str)]) -> TempDir {
+    let dir = TempDir::new().unwrap();
+    for (name, content) in files {
+        let path = dir.path().join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+    dir
+}
+
+/// Relative paths of every file below `dir`, sorted, with `/` separators
+fn files_in(dir: &Path) -> Vec<String> {
+    let mut files: Vec<String> = ignore::WalkBuilder::new(dir)
+        .standard_filters(false)
+        .build()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_some_and(|kind| kind.is_file()))
+        .map(|entry| archive::entry_name(entry.path().strip_prefix(dir).unwrap()))
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn failed_copy_is_reported_and_the_rest_still_copied() {
+    let source = tree(&[("a.txt", "a"), ("b.txt", "b"), ("c.txt", "c")]);
+    let target = TempDir::new().unwrap();
+    let app = app();
+    fail_file(&app, "b.txt", std::io::ErrorKind::PermissionDenied, |_| true);
+    let errors = count_events(&app, "backup-error");
+
+    let result = Backup::new(&[source.path()], target.path()).run(&app).unwrap();
+
+    assert!(!result.success);
+    assert_eq!(result.copied_count, 2);
+    assert_eq!(result.errors.len(), 1);
+    assert!(result.errors[0].file.as_deref().unwrap().ends_with("b.txt"));
+    assert_eq!(errors.load(Ordering::SeqCst), 1);
+    assert_eq!(files_in(target.path()), ["a.txt", "c.txt"]);
+}
+
+#[test]
+fn strict_backup_stops_at_the_first_error() {
+    let source = tree(&[("a.txt", "a"), ("b.txt", "b"), ("c.txt", "c")]);
+    let target = TempDir::new().unwrap();
+    let app = app();
+    fail_file(&app, "b.txt", std::io::ErrorKind::PermissionDenied, |_| true);
+    let errors = count_events(&app, "backup-error");
+
+    let mut backup = Backup::new(&[source.path()], target.path()).in_order();
+    backup.strict = true;
+    let result = backup.run(&app).unwrap();
+
+    assert!(!result.success);
+    assert_eq!(result.copied_count, 1);
+    assert_eq!(errors.load(Ordering::SeqCst), 1);
+    assert!(result.message.starts_with("Stopped at the first error"));
+    assert_eq!(files_in(target.path()), ["a.txt"]);
+}
+
+#[test]
+fn transient_error_is_retried() {
+    let source = tree(&[("a.txt", "a")]);
+    let target = TempDir::new().unwrap();
+    let app = app();
+    fail_file(&app, "a.txt", std::io::ErrorKind::Interrupted, |attempt| attempt < 2);
+    let retries = count_events(&app, "backup-retry");
+
+    let result = Backup::new(&[source.path()], target.path()).run(&app).unwrap();
+
+    assert!(result.success);
+    assert_eq!(retries.load(Ordering::SeqCst), 2);
+    assert_eq!(fs::read_to_string(target.path().join("a.txt")).unwrap(), "a");
+}
+
+#[test]
+fn transient_error_fails_once_retries_run_out() {
+    let source = tree(&[("a.txt", "a")]);
+    let target = TempDir::new().unwrap();
+    let app = app();
+    fail_file(&app, "a.txt", std::io::ErrorKind::Interrupted, |_| true);
+    let retries = count_events(&app, "backup-retry");
+
+    let mut backup = Backup::new(&[source.path()], target.path());
+    backup.max_retries = Some(1);
+    let result = backup.run(&app).unwrap();
+
+    assert!(!result.success);
+    assert_eq!(retries.load(Ordering::SeqCst), 1);
+    assert_eq!(result.errors.len(), 1);
+}
+
+#[test]
+fn failed_atomic_copy_leaves_nothing_behind() {
+    let source = tree(&[("a.txt", "new")]);
+    let target = tree(&[("a.txt", "old")]);
+    let app = app();
+    fail_file(&app, "a.txt", std::io::ErrorKind::PermissionDenied, |_| true);
+
+    let result = Backup::new(&[source.path()], target.path()).run(&app).unwrap();
+
+    assert!(!result.success);
+    // The old file is kept and the temporary copy removed
+    assert_eq!(files_in(target.path()), ["a.txt"]);
+    assert_eq!(fs::read_to_string(target.path().join("a.txt")).unwrap(), "old");
+}
+
+#[test]
+fn temporary_files_of_an_interrupted_run_are_removed() {
+    let source = tree(&[("a.txt", "new")]);
+    let target = tree(&[
+        ("a.txt.m4ssc0py.tmp", "cut short"),
+        ("old/b.txt.m4ssc0py.tmp", "cut short"),
+        ("old/c.txt", "kept"),
+    ]);
+
+    let mut backup = Backup::new(&[source.path()], target.path());
+    backup.atomic = false;
+    assert!(backup.run(&app()).unwrap().success);
+
+    assert_eq!(files_in(target.path()), ["a.txt", "old/c.txt"]);
+}
+
+#[test]
+fn failed_renamed_copy_gives_up_its_name() {
+    let source = tree(&[("a.txt", "new")]);
+    let target = tree(&[("a.txt", "old")]);
+    let app = app();
+    fail_file(&app, "a.txt", std::io::ErrorKind::PermissionDenied, |_| true);
+
+    let mut backup = Backup::new(&[source.path()], target.path());
+    backup.collision_mode = "rename";
+    let result = backup.run(&app).unwrap();
+
+    assert!(!result.success);
+    assert_eq!(files_in(target.path()), ["a.txt"]);
+    assert_eq!(fs::read_to_string(target.path().join("a.txt")).unwrap(), "old");
+}
+
+/// A filter with the given patterns, matching case-sensitively
+fn filter(blacklist: &[&str], whitelist: &[&str]) -> FileFilter {
+    let list = |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+    FileFilter::new(&list(blacklist), &list(whitelist), false)
+}
+
+#[test]
+fn brace_sets_match_in_the_blacklist() {
+    let filter = filter(&["*.{log,tmp}"], &[]);
+    assert!(filter.is_excluded(Path::new("debug.log")));
+    assert!(filter.is_excluded(Path::new("cache/data.tmp")));
+    assert!(!filter.is_excluded(Path::new("notes.txt")));
+    assert!(filter.warnings.is_empty());
+}
+
+#[test]
+fn brace_sets_match_in_the_whitelist() {
+    let filter = filter(&[], &["*.{jpg,png}"]);
+    assert!(filter.is_wanted(Path::new("photo.jpg")));
+    assert!(filter.is_wanted(Path::new("albums/2024/photo.png")));
+    assert!(!filter.is_wanted(Path::new("photo.gif")));
+}
+
+#[test]
+fn unclosed_brace_is_matched_literally() {
+    let filter = filter(&["{draft"], &[]);
+    assert!(filter.is_excluded(Path::new("notes/{draft")));
+    assert!(!filter.is_excluded(Path::new("draft")));
+    assert_eq!(filter.warnings.len(), 1);
+}
+
+/// A relative path of nested folders more than 300 characters long
+fn deep_path() -> PathBuf {
+    let folder = "a".repeat(60);
+    let mut path: PathBuf = (0..6).map(|_| folder.as_str()).collect();
+    path.push("file.txt");
+    path
+}
+
+#[test]
+fn files_beyond_max_path_are_copied() {
+    let relative = deep_path();
+    let source = TempDir::new().unwrap();
+    let file = source.path().join(&relative);
+    fs::create_dir_all(long_path(file.parent().unwrap())).unwrap();
+    fs::write(long_path(&file), "deep").unwrap();
+    let target = TempDir::new().unwrap();
+    assert!(target.path().join(&relative).as_os_str().len() > 260);
+
+    let result = Backup::new(&[source.path()], target.path()).run(&app()).unwrap();
+
+    assert!(result.success, "{:?}", result.errors.iter().map(|e| &e.message).collect::<Vec<_>>());
+    let copied = fs::read_to_string(long_path(&target.path().join(&relative))).unwrap();
+    assert_eq!(copied, "deep");
+}
+
+#[cfg(windows)]
+#[test]
+fn long_path_prefixes_drive_paths() {
+    let path = long_path(Path::new(r"C:\Users\me\..\you\.\file.txt"));
+    assert_eq!(path.as_os_str(), r"\\?\C:\Users\you\file.txt");
+    let path = long_path(Path::new("C:/Users/me/file.txt"));
+    assert_eq!(path.as_os_str(), r"\\?\C:\Users\me\file.txt");
+}
+
+#[cfg(windows)]
+#[test]
+fn long_path_prefixes_unc_paths() {
+    let path = long_path(Path::new(r"\\server\share\backups\file.txt"));
+    assert_eq!(path.as_os_str(), r"\\?\UNC\server\share\backups\file.txt");
+}
+
+#[cfg(windows)]
+#[test]
+fn long_path_leaves_relative_and_verbatim_paths() {
+    for path in [r"backups\file.txt", r"\\?\C:\file.txt", r"\\.\pipe\name"] {
+        assert_eq!(long_path(Path::new(path)).as_os_str(), path);
+    }
+}
+
+#[cfg(windows)]
+#[test]
+fn long_path_reaches_files_beyond_max_path() {
+    let dir = TempDir::new().unwrap();
+    let file = dir.path().join(deep_path());
+    assert!(file.as_os_str().len() > 260);
+    fs::create_dir_all(long_path(file.parent().unwrap())).unwrap();
+    fs::write(long_path(&file), "deep").unwrap();
+    assert_eq!(fs::read_to_string(long_path(&file)).unwrap(), "deep");
+}
+
+#[test]
+fn overwriting_a_deduplicated_file_leaves_its_twin_alone() {
+    // Throttling and hashing copy through the chunked loop, the rest don't
+    for (prefer_reflink, verify) in [(false, false), (false, true), (true, false)] {
+        let source = tree(&[("a.txt", "same"), ("b.txt", "same")]);
+        let target = TempDir::new().unwrap();
+        let mut backup = Backup::new(&[source.path()], target.path()).in_order();
+        backup.dedupe = true;
+        assert!(backup.run(&app()).unwrap().success);
+
+        fs::write(source.path().join("a.txt"), "changed").unwrap();
+        let mut backup = Backup::new(&[source.path()], target.path()).in_order();
+        backup.atomic = false;
+        backup.prefer_reflink = Some(prefer_reflink);
+        backup.verify = verify;
+        assert!(backup.run(&app()).unwrap().success);
+
+        assert_eq!(fs::read_to_string(target.path().join("a.txt")).unwrap(), "changed");
+        assert_eq!(fs::read_to_string(target.path().join("b.txt")).unwrap(), "same");
+    }
+}
+
+#[test]
+fn concurrent_copies_to_one_name_each_get_their_own() {
+    const COPIES: usize = 64;
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    let dest = target.path().join("a.txt");
+    let barrier = std::sync::Barrier::new(COPIES);
+
+    std::thread::scope(|scope| {
+        for i in 0..COPIES {
+            let file = source.path().join(format!("{i}.txt"));
+            fs::write(&file, i.to_string()).unwrap();
+            let (dest, barrier) = (&dest, &barrier);
+            scope.spawn(move || {
+                barrier.wait();
+                let name = find_available_name(dest, None, false);
+                // Reflinking, hashing and plain copies all write over the claim
+                let (prefer_reflink, hash) = [(true, false), (false, true), (false, false)][i % 3];
+                copy_file(&file, &name, false, prefer_reflink, hash, &ChunkedCopy::default())
+                    .unwrap();
+            });
+        }
+    });
+
+    let mut contents: Vec<usize> = files_in(target.path())
+        .iter()
+        .map(|name| fs::read_to_string(target.path().join(name)).unwrap().parse().unwrap())
+        .collect();
+    contents.sort();
+    assert_eq!(contents, (0..COPIES).collect::<Vec<_>>());
+}
+
+#[test]
+fn renamed_copies_from_many_sources_are_all_kept() {
+    let sources: Vec<TempDir> = (0..8).map(|i| tree(&[("a.txt", &i.to_string())])).collect();
+    let target = tree(&[("a.txt", "old")]);
+    let paths: Vec<&Path> = sources.iter().map(TempDir::path).collect();
+
+    let mut backup = Backup::new(&paths, target.path());
+    backup.collision_mode = "rename";
+    backup.concurrency = Some(8);
+    backup.dedupe = true;
+    assert!(backup.run(&app()).unwrap().success);
+
+    let mut contents: Vec<String> = files_in(target.path())
+        .iter()
+        .map(|name| fs::read_to_string(target.path().join(name)).unwrap())
+        .collect();
+    contents.sort();
+    let mut expected: Vec<String> = (0..8).map(|i| i.to_string()).collect();
+    expected.push("old".to_string());
+    assert_eq!(contents, expected);
+}
+
+#[test]
+fn changed_files_leave_the_linked_snapshot_alone() {
+    let source = tree(&[("a.txt", "first"), ("b.txt", "kept")]);
+    let old = TempDir::new().unwrap();
+    assert!(Backup::new(&[source.path()], old.path()).run(&app()).unwrap().success);
+    let new = TempDir::new().unwrap();
+    let mut backup = Backup::new(&[source.path()], new.path());
+    backup.link_dest = Some(old.path().to_path_buf());
+    assert!(backup.run(&app()).unwrap().success);
+
+    // Appending allows resuming, verifying copies through the chunked loop
+    fs::write(source.path().join("a.txt"), "first, then more").unwrap();
+    for verify in [false, true] {
+        let mut backup = Backup::new(&[source.path()], new.path());
+        backup.link_dest = Some(old.path().to_path_buf());
+        backup.atomic = false;
+        backup.resume_partial = !verify;
+        backup.verify = verify;
+        assert!(backup.run(&app()).unwrap().success);
+
+        let read = |dir: &TempDir| fs::read_to_string(dir.path().join("a.txt")).unwrap();
+        assert_eq!(read(&new), "first, then more");
+        assert_eq!(read(&old), "first");
+        // Put the link back for the next round
+        fs::remove_file(new.path().join("a.txt")).unwrap();
+        fs::hard_link(old.path().join("a.txt"), new.path().join("a.txt")).unwrap();
+    }
+}
+
+#[test]
+fn reencrypting_a_deduplicated_file_leaves_its_twin_alone() {
+    let source = tree(&[("a.txt", "same"), ("b.txt", "same")]);
+    let target = TempDir::new().unwrap();
+    let mut backup = Backup::new(&[source.path()], target.path()).in_order();
+    backup.dedupe = true;
+    backup.encrypt = Some("password".to_string());
+    assert!(backup.run(&app()).unwrap().success);
+    let twin = fs::read(target.path().join("b.txt.enc")).unwrap();
+
+    // Every copy is encrypted differently, so the twin is left out of the run
+    fs::write(source.path().join("a.txt"), "changed").unwrap();
+    backup.dedupe = false;
+    backup.atomic = false;
+    backup.blacklist = vec!["b.txt".to_string()];
+    assert!(backup.run(&app()).unwrap().success);
+
+    assert_eq!(fs::read(target.path().join("b.txt.enc")).unwrap(), twin);
+    assert_ne!(fs::read(target.path().join("a.txt.enc")).unwrap(), twin);
+}
+
+#[test]
+fn encrypting_warns_that_copies_are_not_verified_and_rejects_incremental() {
+    let source = tree(&[("a.txt", "secret")]);
+    let target = TempDir::new().unwrap();
+    let app = app();
+    let warnings = count_events(&app, "backup-warning");
+    let mut backup = Backup::new(&[source.path()], target.path());
+    backup.encrypt = Some("password".to_string());
+    backup.verify = true;
+    assert!(backup.run(&app).unwrap().success);
+    assert_eq!(warnings.load(Ordering::SeqCst), 1);
+
+    backup.incremental = true;
+    assert!(backup.run(&app).is_err());
+}
+
+#[test]
+fn restoring_an_encrypted_backup_is_refused() {
+    let backup = tree(&[("a.txt.enc", "ciphertext")]);
+    let original = TempDir::new().unwrap();
+    let entry = ManifestEntry {
+        path: "a.txt.enc".to_string(),
+        source: original.path().join("a.txt").to_string_lossy().to_string(),
+        size: 10,
+        modified: None,
+        checksum: None,
+    };
+    let manifest_path = original.path().join("manifest.json");
+    let app = app();
+    for encrypted in [true, false] {
+        let manifest = Manifest::new(backup.path(), vec![entry.clone()], encrypted);
+        manifest.write(&manifest_path).unwrap();
+        let result = tauri::async_runtime::block_on(restore_backup(
+            app.handle().clone(),
+            app.state::<BackupControl>(),
+            manifest_path.to_string_lossy().to_string(),
+            None,
+            "overwrite".to_string(),
+            false,
+        ));
+        assert_eq!(result.is_err(), encrypted);
+        assert_eq!(original.path().join("a.txt").exists(), !encrypted);
+    }
+}
+
+/// Check one source against one target, in mirror mode or not
+fn overlap(
+    source: &Path,
+    target: &Path,
+    depth: usize,
+    mirror: bool,
+) -> Result<Vec<PathBuf>, String> {
+    let sources = [source.to_string_lossy().to_string()];
+    check_overlap(&sources, &[target.to_path_buf()], depth, mirror, None)
+}
+
+#[test]
+fn overlap_rejects_a_target_equal_to_the_source() {
+    let dir = tree(&[("src/a.txt", "a")]);
+    let source = dir.path().join("src");
+    for target in [source.clone(), source.join("sub").join("..")] {
+        let error = overlap(&source, &target, 0, false).unwrap_err();
+        assert!(error.starts_with("Target is the same as source"), "{error}");
+    }
+}
+
+#[test]
+fn overlap_skips_a_target_inside_the_source() {
+    let dir = tree(&[("src/a.txt", "a")]);
+    let source = dir.path().join("src");
+    let target = source.join("backup");
+
+    let skipped = overlap(&source, &target, 0, false).unwrap();
+    assert_eq!(skipped, [canonical_path(&target)]);
+    // Deeper inside, where the walk reaches it too
+    let skipped = overlap(&source, &target.join("nested"), 0, true).unwrap();
+    assert_eq!(skipped, [canonical_path(&target.join("nested"))]);
+}
+
+#[test]
+fn overlap_checks_a_source_inside_the_target() {
+    let dir = tree(&[("target/src/a.txt", "a")]);
+    let target = dir.path().join("target");
+    let source = target.join("src");
+
+    assert_eq!(overlap(&source, &target, 0, false).unwrap(), Vec::<PathBuf>::new());
+    let error = overlap(&source, &target, 0, true).unwrap_err();
+    assert!(error.starts_with("Mirror mode would delete source"), "{error}");
+    // With its folder kept, the source lands on itself
+    let error = overlap(&source, &target, 1, false).unwrap_err();
+    assert!(error.starts_with("Source would be copied onto itself"), "{error}");
+}
+
+#[test]
+fn overlap_tells_sibling_folders_with_a_common_prefix_apart() {
+    let dir = tree(&[("b/a.txt", "a"), ("bc/a.txt", "a")]);
+    let (short, long) = (dir.path().join("b"), dir.path().join("bc"));
+    for (source, target) in [(&short, &long), (&long, &short)] {
+        for mirror in [false, true] {
+            assert_eq!(overlap(source, target, 0, mirror).unwrap(), Vec::<PathBuf>::new());
+        }
+    }
+}
+
+#[test]
+fn newer_mode_only_skips_when_both_times_are_known() {
+    assert_eq!(is_newer_mtime(Some(2), Some(1)), Some(true));
+    assert_eq!(is_newer_mtime(Some(1), Some(1)), Some(false));
+    assert_eq!(is_newer_mtime(Some(1), Some(2)), Some(false));
+    // An unknown time used to compare as older than any other
+    assert_eq!(is_newer_mtime(None, Some(1)), None);
+    assert_eq!(is_newer_mtime(Some(1), None), None);
+    assert_eq!(is_newer_mtime(None, None), None);
+}
+
+#[test]
+fn newer_mode_replaces_only_older_copies() {
+    let source = tree(&[("old.txt", "new"), ("new.txt", "stale")]);
+    let target = tree(&[("old.txt", "old"), ("new.txt", "fresh")]);
+    let hour_ago = FileTime::from_unix_time(FileTime::now().unix_seconds() - 3600, 0);
+    filetime::set_file_mtime(target.path().join("old.txt"), hour_ago).unwrap();
+    filetime::set_file_mtime(source.path().join("new.txt"), hour_ago).unwrap();
+
+    let mut backup = Backup::new(&[source.path()], target.path());
+    backup.collision_mode = "newer";
+    let result = backup.run(&app()).unwrap();
+
+    assert!(result.success);
+    assert_eq!(fs::read_to_string(target.path().join("old.txt")).unwrap(), "new");
+    assert_eq!(fs::read_to_string(target.path().join("new.txt")).unwrap(), "fresh");
+}
+
+#[test]
+fn single_file_source_is_copied_into_the_target() {
+    let dir = tree(&[("docs/report.txt", "report"), ("docs/other.txt", "other")]);
+    let file = dir.path().join("docs").join("report.txt");
+    let target = TempDir::new().unwrap();
+
+    let result = Backup::new(&[&file], target.path()).run(&app()).unwrap();
+
+    assert!(result.success);
+    assert_eq!(result.copied_count, 1);
+    assert_eq!(files_in(target.path()), ["report.txt"]);
+}
+
+#[test]
+fn single_file_source_keeps_its_folder_with_include_source_dir() {
+    let dir = tree(&[("docs/report.txt", "report"), ("docs/other.txt", "other")]);
+    let file = dir.path().join("docs").join("report.txt");
+    let target = TempDir::new().unwrap();
+
+    let mut backup = Backup::new(&[&file], target.path());
+    backup.include_source_dir = true;
+    let result = backup.run(&app()).unwrap();
+
+    assert!(result.success);
+    assert_eq!(files_in(target.path()), ["docs/report.txt"]);
+    assert_eq!(fs::read_to_string(target.path().join("docs/report.txt")).unwrap(), "report");
+}
+
+#[test]
+fn single_file_without_a_folder_is_placed_in_one_named_after_it() {
+    assert_eq!(source_folder(Path::new("report.txt"), 1), Path::new("report"));
+    assert_eq!(source_folder(Path::new("report.txt"), 0), Path::new(""));
+}
+
+#[test]
+fn anchored_star_stays_in_the_source_root() {
+    let filter = filter(&["/*.log"], &[]);
+    assert!(filter.is_excluded(Path::new("a.log")));
+    assert!(!filter.is_excluded(Path::new("src/a.log")));
+    assert!(!filter.is_excluded(Path::new("src/deep/a.log")));
+}
+
+#[test]
+fn anchored_folder_is_only_the_one_in_the_source_root() {
+    let anchored = filter(&["/build"], &[]);
+    assert!(anchored.is_excluded(Path::new("build")));
+    assert!(anchored.is_excluded(Path::new("build/out/app.bin")));
+    assert!(!anchored.is_excluded(Path::new("src/build")));
+    assert!(!anchored.is_excluded(Path::new("src/build/app.bin")));
+    assert!(!anchored.is_excluded(Path::new("builder")));
+
+    let anywhere = filter(&["build"], &[]);
+    assert!(anywhere.is_excluded(Path::new("build/out/app.bin")));
+    assert!(anywhere.is_excluded(Path::new("src/build/app.bin")));
+    assert!(!anywhere.is_excluded(Path::new("builder")));
+}
+
+#[test]
+fn anchored_patterns_can_name_nested_paths() {
+    let filter = filter(&["/src/*.log", "/docs/**/*.tmp", "!/src/keep.log"], &[]);
+    assert!(filter.is_excluded(Path::new("src/a.log")));
+    assert!(!filter.is_excluded(Path::new("src/keep.log")));
+    assert!(!filter.is_excluded(Path::new("src/sub/a.log")));
+    assert!(filter.is_excluded(Path::new("docs/a/b/c.tmp")));
+    assert!(!filter.is_excluded(Path::new("other/docs/c.tmp")));
+}
+
+#[test]
+fn anchored_invalid_pattern_is_matched_literally_in_the_root() {
+    let filter = filter(&["/{draft"], &[]);
+    assert!(filter.is_excluded(Path::new("{draft/notes.txt")));
+    assert!(!filter.is_excluded(Path::new("notes/{draft")));
+}
+
+/// A relative path joined with the platform's separator
+fn joined(parts: &[&str]) -> PathBuf {
+    parts.iter().collect()
+}
+
+#[test]
+fn nested_patterns_match_the_full_relative_path() {
+    let filter = filter(&["a/b/*.txt"], &[]);
+    assert!(filter.is_excluded(&joined(&["a", "b", "c.txt"])));
+    assert!(!filter.is_excluded(&joined(&["a", "c.txt"])));
+    assert!(!filter.is_excluded(&joined(&["x", "a", "b", "c.txt"])));
+    assert!(!filter.is_excluded(&joined(&["a", "b", "c.md"])));
+}
+
+#[test]
+fn double_star_folders_match_at_any_depth() {
+    let filter = filter(&["**/node_modules"], &[]);
+    assert!(filter.is_excluded(&joined(&["node_modules"])));
+    assert!(filter.is_excluded(&joined(&["app", "node_modules"])));
+    assert!(filter.is_excluded(&joined(&["a", "b", "c", "node_modules", "pkg", "index.js"])));
+    assert!(!filter.is_excluded(&joined(&["a", "b", "node_modules_backup", "index.js"])));
+}
+
+#[test]
+fn nested_whitelist_patterns_match_the_full_relative_path() {
+    let filter = filter(&[], &["photos/*/raw/*.cr2"]);
+    assert!(filter.is_wanted(&joined(&["photos", "2024", "raw", "img.cr2"])));
+    assert!(!filter.is_wanted(&joined(&["photos", "2024", "img.cr2"])));
+}
+
+#[test]
+fn nested_patterns_leave_out_files_in_a_backup() {
+    let source = tree(&[
+        ("a/b/c.txt", "c"),
+        ("a/b/c.md", "c"),
+        ("a/c.txt", "c"),
+        ("web/app/node_modules/pkg/index.js", "js"),
+        ("web/app/main.js", "js"),
+    ]);
+    let target = TempDir::new().unwrap();
+
+    let mut backup = Backup::new(&[source.path()], target.path());
+    backup.blacklist = vec!["a/b/*.txt".to_string(), "**/node_modules".to_string()];
+    assert!(backup.run(&app()).unwrap().success);
+
+    assert_eq!(files_in(target.path()), ["a/b/c.md", "a/c.txt", "web/app/main.js"]);
+}
+
+/// Whether a file is readonly
+fn is_readonly(path: &Path) -> bool {
+    fs::metadata(path).unwrap().permissions().readonly()
+}
+
+/// Make a file readonly, or writable again
+fn set_readonly(path: &Path, readonly: bool) {
+    let mut permissions = fs::metadata(path).unwrap().permissions();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        permissions.set_mode(if readonly { 0o444 } else { 0o644 });
+    }
+    #[cfg(not(unix))]
+    permissions.set_readonly(readonly);
+    fs::set_permissions(path, permissions).unwrap();
+}
+
+#[test]
+fn readonly_destination_is_overwritten() {
+    for atomic in [false, true] {
+        for source_readonly in [false, true] {
+            let source = tree(&[("a.txt", "new")]);
+            let target = tree(&[("a.txt", "old")]);
+            set_readonly(&target.path().join("a.txt"), true);
+            set_readonly(&source.path().join("a.txt"), source_readonly);
+
+            let mut backup = Backup::new(&[source.path()], target.path());
+            backup.atomic = atomic;
+            let result = backup.run(&app()).unwrap();
+
+            assert!(result.success, "{:?}", result.errors.first().map(|e| &e.message));
+            let dest = target.path().join("a.txt");
+            assert_eq!(fs::read_to_string(&dest).unwrap(), "new");
+            // The source's permissions are copied over afterwards
+            assert_eq!(is_readonly(&dest), source_readonly);
+            // So the folders can be cleaned up on Windows
+            set_readonly(&dest, false);
+        }
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn making_a_hardlinked_file_writable_leaves_its_permissions_alone() {
+    let dir = tree(&[("a.txt", "a")]);
+    let (file, link) = (dir.path().join("a.txt"), dir.path().join("b.txt"));
+    fs::hard_link(&file, &link).unwrap();
+    set_readonly(&file, true);
+
+    make_writable(&link).unwrap();
+    assert!(is_readonly(&file));
+    fs::remove_file(&link).unwrap();
+    make_writable(&file).unwrap();
+    assert!(!is_readonly(&file));
+}
+
+/// The walk settings of a plain backup
+fn walk_options(respect_gitignore: bool) -> WalkOptions {
+    WalkOptions {
+        include_hidden: true,
+        respect_gitignore,
+        respect_backupignore: true,
+        symlink_mode: SymlinkMode::Copy,
+        max_depth: None,
+        skip_paths: Vec::new(),
+        copy_special_files: false,
+    }
+}
+
+/// Files and bytes counted below one source
+fn count(source: &Path, filter: &FileFilter, respect_gitignore: bool) -> (u64, u64) {
+    let path = source.to_string_lossy();
+    let sources = [Source { path: &path, filter }];
+    count_files_multi(&sources, &walk_options(respect_gitignore), &mut |_, _| {})
+}
+
+#[test]
+fn counting_leaves_out_blacklisted_files() {
+    let source = tree(&[("a.txt", "a"), ("b.log", "bb"), ("node_modules/x.js", "xxx")]);
+    assert_eq!(count(source.path(), &filter(&[], &[]), false), (3, 6));
+    assert_eq!(count(source.path(), &filter(&["*.log", "node_modules"], &[]), false), (1, 1));
+    assert_eq!(count(source.path(), &filter(&[], &["*.js"]), false), (1, 3));
+}
+
+#[test]
+fn counting_respects_gitignore_when_asked() {
+    let source = tree(&[(".gitignore", "*.log\n"), ("a.txt", "a"), ("b.log", "bb")]);
+    fs::create_dir(source.path().join(".git")).unwrap();
+    assert_eq!(count(source.path(), &filter(&[], &[]), true), (2, 7));
+    assert_eq!(count(source.path(), &filter(&[], &[]), false), (3, 9));
+}
+
+#[test]
+fn counting_a_single_file_source() {
+    let dir = tree(&[("a.txt", "a"), ("b.txt", "bb")]);
+    let file = dir.path().join("b.txt");
+    assert_eq!(count(&file, &filter(&[], &[]), false), (1, 2));
+    assert_eq!(count(&file, &filter(&["b.*"], &[]), false), (0, 0));
+    assert_eq!(count(&file, &filter(&[], &["*.md"]), false), (0, 0));
+}
+
+#[test]
+fn counting_adds_up_every_source() {
+    let (first, second) = (tree(&[("a.txt", "a")]), tree(&[("b.txt", "bb"), ("c.txt", "c")]));
+    let filter = filter(&[], &[]);
+    let first_path = first.path().to_string_lossy();
+    let second_path = second.path().to_string_lossy();
+    let sources = [
+        Source { path: &first_path, filter: &filter },
+        Source { path: &second_path, filter: &filter },
+    ];
+    let totals = count_files_multi(&sources, &walk_options(false), &mut |_, _| {});
+    assert_eq!(totals, (3, 4));
+}
+
+#[test]
+fn renamed_files_of_an_earlier_run_are_not_foreign() {
+    let source = tree(&[("report.txt", "report"), ("notes.txt", "notes")]);
+    let file = source.path().join("report.txt").to_string_lossy().to_string();
+
+    let runs = [
+        (false, ["final.txt", "notes.txt"]),
+        (true, ["final.txt.enc", "notes.txt.enc"]),
+    ];
+    for (encrypt, names) in runs {
+        let target = TempDir::new().unwrap();
+        let mut backup = Backup::new(&[source.path()], target.path());
+        backup.name_overrides = Some(HashMap::from([(file.clone(), "final.txt".to_string())]));
+        backup.fail_on_existing_foreign = true;
+        backup.encrypt = encrypt.then(|| "password".to_string());
+        assert!(backup.run(&app()).unwrap().success);
+        // Running again finds nothing but its own files
+        assert!(backup.run(&app()).unwrap().success);
+        assert_eq!(files_in(target.path()), names);
+
+        fs::write(target.path().join("stray.txt"), "stray").unwrap();
+        let Err(error) = backup.run(&app()) else { panic!("stray file not found") };
+        assert!(error.contains("stray.txt"), "{error}");
+        assert!(!error.contains("final.txt"), "{error}");
+    }
+}
+
+#[test]
+fn gitignore_of_the_enclosing_repository_applies_when_asked() {
+    let repo = tree(&[
+        (".gitignore", "*.log\n/src/generated\n"),
+        ("src/app/main.rs", "main"),
+        ("src/app/debug.log", "log"),
+        ("src/generated/out.rs", "out"),
+    ]);
+    fs::create_dir(repo.path().join(".git")).unwrap();
+    let source = repo.path().join("src");
+
+    for respect_gitignore in [true, false] {
+        let target = TempDir::new().unwrap();
+        let mut backup = Backup::new(&[&source], target.path());
+        backup.respect_gitignore = respect_gitignore;
+        assert!(backup.run(&app()).unwrap().success);
+
+        let expected: &[&str] = match respect_gitignore {
+            true => &["app/main.rs"],
+            false => &["app/debug.log", "app/main.rs", "generated/out.rs"],
+        };
+        assert_eq!(files_in(target.path()), expected);
+    }
+}
+
+#[test]
+fn ignore_files_above_the_source_only_apply_with_gitignore() {
+    let dir = tree(&[
+        (".ignore", "*.tmp\n"),
+        (BACKUP_IGNORE_FILENAME, "*.log\n"),
+        ("src/a.log", "log"),
+        ("src/a.tmp", "tmp"),
+        ("src/a.txt", "txt"),
+    ]);
+    let source = dir.path().join("src");
+
+    for respect_gitignore in [false, true] {
+        let target = TempDir::new().unwrap();
+        let mut backup = Backup::new(&[&source], target.path());
+        backup.respect_gitignore = respect_gitignore;
+        assert!(backup.run(&app()).unwrap().success);
+
+        let expected: &[&str] = match respect_gitignore {
+            true => &["a.txt"],
+            false => &["a.log", "a.tmp", "a.txt"],
+        };
+        assert_eq!(files_in(target.path()), expected);
+    }
+}
+
+/// A folder holding the given files, each modified `age` seconds ago, so
+/// fill mode copies them in list order
+fn aged_tree(files: &[(&str, &str)]) -> TempDir {
+    let dir = tree(files);
+    let now = FileTime::now().unix_seconds();
+    for (age, (name, _)) in files.iter().enumerate() {
+        let time = FileTime::from_unix_time(now - 60 * (age as i64 + 1), 0);
+        filetime::set_file_mtime(dir.path().join(name), time).unwrap();
+    }
+    dir
+}
+
+/// A backup in fill mode, copying one file at a time
+fn fill(source: &Path, target: &Path) -> Backup {
+    let mut backup = Backup::new(&[source], target);
+    backup.fill_mode = true;
+    backup.concurrency = Some(1);
+    backup
+}
+
+#[test]
+fn fill_mode_leaves_out_the_rest_once_the_target_is_full() {
+    let source = aged_tree(&[("a.txt", "a"), ("b.txt", "b"), ("c.txt", "c")]);
+    let target = TempDir::new().unwrap();
+    let app = app();
+    fail_file(&app, "b.txt", std::io::ErrorKind::StorageFull, |_| true);
+
+    let result = fill(source.path(), target.path()).run(&app).unwrap();
+
+    assert!(result.errors.is_empty(), "{:?}", result.errors.first().map(|e| &e.message));
+    assert_eq!(result.copied_count, 1);
+    assert_eq!(result.left_out_count, 2);
+    assert_eq!(files_in(target.path()), ["a.txt"]);
+}
+
+#[test]
+fn fill_mode_still_reports_other_errors() {
+    let source = aged_tree(&[("a.txt", "a"), ("b.txt", "b"), ("c.txt", "c")]);
+    let target = TempDir::new().unwrap();
+    let app = app();
+    fail_file(&app, "b.txt", std::io::ErrorKind::PermissionDenied, |_| true);
+
+    let result = fill(source.path(), target.path()).run(&app).unwrap();
+
+    assert!(!result.success);
+    assert_eq!(result.errors.len(), 1);
+    assert_eq!(result.left_out_count, 0);
+    assert_eq!(files_in(target.path()), ["a.txt", "c.txt"]);
+}
+
+#[test]
+fn fill_mode_keeps_the_old_copy_when_replacing_it_runs_out_of_space() {
+    let source = aged_tree(&[("a.txt", "new"), ("b.txt", "b")]);
+    for atomic in [false, true] {
+        let target = tree(&[("a.txt", "old")]);
+        let app = app();
+        fail_file(&app, "a.txt", std::io::ErrorKind::StorageFull, |_| true);
+
+        let mut backup = fill(source.path(), target.path());
+        backup.atomic = atomic;
+        let result = backup.run(&app).unwrap();
+
+        assert_eq!(result.left_out_count, 2);
+        assert_eq!(files_in(target.path()), ["a.txt"]);
+        // The fault comes once the data is written, which replaced the old
+        // copy in place, so only an atomic copy can still have it
+        if atomic {
+            assert_eq!(fs::read_to_string(target.path().join("a.txt")).unwrap(), "old");
+        }
+    }
+}
+
+#[test]
+fn fill_mode_removes_a_new_file_cut_short() {
+    let source = aged_tree(&[("a.txt", "a")]);
+    let target = TempDir::new().unwrap();
+    let app = app();
+    fail_file(&app, "a.txt", std::io::ErrorKind::StorageFull, |_| true);
+
+    let mut backup = fill(source.path(), target.path());
+    backup.atomic = false;
+    let result = backup.run(&app).unwrap();
+
+    assert_eq!(result.left_out_count, 1);
+    assert_eq!(files_in(target.path()), Vec::<String>::new());
+}
+
+#[test]
+fn fill_mode_rejects_mirror() {
+    let source = tree(&[("a.txt", "a")]);
+    let target = TempDir::new().unwrap();
+    let mut backup = fill(source.path(), target.path());
+    backup.mirror = true;
+    let Err(error) = backup.run(&app()) else { panic!("fill mode ran with mirror") };
+    assert!(error.contains("mirror"), "{error}");
+}
+
+#[test]
+fn fill_budget_only_counts_what_a_file_adds() {
+    let (first, second) = (tree(&[("a.txt", "1234")]), tree(&[("a.txt", "12345678")]));
+    let roots = [first.path().to_path_buf(), second.path().to_path_buf()];
+    let relative = Path::new("a.txt");
+
+    assert_eq!(fill_growth(&roots, Path::new("new.txt"), 10, "overwrite"), 10);
+    // The smaller copy grows the most
+    assert_eq!(fill_growth(&roots, relative, 10, "overwrite"), 6);
+    assert_eq!(fill_growth(&roots, relative, 2, "overwrite"), 0);
+    assert_eq!(fill_growth(&roots, relative, 10, "skip"), 0);
+    assert_eq!(fill_growth(&roots, relative, 10, "rename"), 10);
+}
+
+#[test]
+fn remote_folders_are_created_from_the_top_with_slashes() {
+    assert_eq!(
+        sftp::remote_ancestors(Path::new("/backup/photos/2024")),
+        ["/", "/backup", "/backup/photos", "/backup/photos/2024"]
+    );
+    assert_eq!(sftp::remote_ancestors(Path::new("backup/photos")), ["backup", "backup/photos"]);
+}
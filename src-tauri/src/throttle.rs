@@ -0,0 +1,207 @@
+//! Chunked copying for large files, for copies that are bandwidth limited
+//! or time limited, and for copies that hash the data on the way.
+
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Largest chunk written by a throttled copy, so the rate stays smooth
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Buffer size used unless configured otherwise
+pub(crate) const DEFAULT_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Files at least this big are copied with the configured buffer instead of
+/// `fs::copy`
+const LARGE_FILE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Bandwidth limit shared by all copy workers, as a token bucket holding at
+/// most one second worth of bytes. Callers may
+/// overdraw it and then sleep until the debt is paid back, which keeps the
+/// average rate at the limit without splitting chunks.
+pub(crate) struct RateLimiter {
+    bytes_per_second: u64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: u64) -> Self {
+        RateLimiter {
+            bytes_per_second: bytes_per_second.max(1),
+            bucket: Mutex::new(Bucket {
+                tokens: 0.0,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    /// Account for `bytes` just transferred, sleeping if the caller is ahead
+    /// of the allowed rate
+    pub fn consume(&self, bytes: u64) {
+        let rate = self.bytes_per_second as f64;
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * rate).min(rate) - bytes as f64;
+            bucket.refilled = now;
+
+            if bucket.tokens < 0.0 {
+                Duration::from_secs_f64(-bucket.tokens / rate)
+            } else {
+                Duration::ZERO
+            }
+        };
+
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+}
+
+/// Settings that require copying chunk by chunk instead of using `fs::copy`
+pub(crate) struct ChunkedCopy {
+    pub limiter: Option<Arc<RateLimiter>>,
+    /// Longest time a single file may take before its copy is abandoned
+    pub timeout: Option<Duration>,
+    /// Bytes read and written at a time
+    pub buffer_size: usize,
+}
+
+impl Default for ChunkedCopy {
+    fn default() -> Self {
+        ChunkedCopy {
+            limiter: None,
+            timeout: None,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+        }
+    }
+}
+
+impl ChunkedCopy {
+    /// Whether a file of `size` bytes has to be copied through `copy`
+    pub fn is_needed(&self, size: u64) -> bool {
+        self.limiter.is_some() || self.timeout.is_some() || size >= LARGE_FILE_SIZE
+    }
+
+    /// Copy `source` to `dest`, also returning the hash of the copied bytes
+    /// when `hash` is set. With a timeout the copy runs on its own thread, so
+    /// even a read that never returns can't stall the caller; the thread is
+    /// told to stop and left to finish on its own.
+    ///
+    /// A `resume_from` above 0 treats that many bytes of `dest` as already
+    /// copied and appends the rest. The size and hash returned still cover
+    /// the whole file.
+    pub fn copy(
+        &self,
+        source: &Path,
+        dest: &Path,
+        hash: bool,
+        resume_from: u64,
+    ) -> io::Result<(u64, Option<blake3::Hash>)> {
+        let chunk_size = match self.limiter {
+            Some(_) => self.buffer_size.min(CHUNK_SIZE),
+            None => self.buffer_size,
+        }
+        .max(1);
+
+        let Some(timeout) = self.timeout else {
+            let limiter = self.limiter.as_deref();
+            return copy_chunks(source, dest, chunk_size, hash, resume_from, limiter, None);
+        };
+
+        let abort = Arc::new(AtomicBool::new(false));
+        let (result_tx, result_rx) = mpsc::channel();
+        let source = source.to_path_buf();
+        let dest = dest.to_path_buf();
+        let limiter = self.limiter.clone();
+        let thread_abort = Arc::clone(&abort);
+        thread::spawn(move || {
+            let limiter = limiter.as_deref();
+            let abort = Some(&*thread_abort);
+            let result =
+                copy_chunks(&source, &dest, chunk_size, hash, resume_from, limiter, abort);
+            let _ = result_tx.send(result);
+        });
+
+        match result_rx.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(_) => {
+                abort.store(true, Ordering::SeqCst);
+                // Not `TimedOut`, which would be retried as a transient error
+                Err(io::Error::other(format!(
+                    "Copy timed out after {} seconds",
+                    timeout.as_secs()
+                )))
+            }
+        }
+    }
+}
+
+/// Copy `source` to `dest` in chunks of `chunk_size` bytes, hashing them if
+/// `hash` is set, throttled by `limiter` and stopping early once `abort` is
+/// set. The first `resume_from` bytes are taken to be in `dest` already.
+/// Like `fs::copy`, the source's permissions are carried over.
+fn copy_chunks(
+    source: &Path,
+    dest: &Path,
+    chunk_size: usize,
+    hash: bool,
+    resume_from: u64,
+    limiter: Option<&RateLimiter>,
+    abort: Option<&AtomicBool>,
+) -> io::Result<(u64, Option<blake3::Hash>)> {
+    let mut hasher = hash.then(blake3::Hasher::new);
+    let mut reader = fs::File::open(source)?;
+    let permissions = reader.metadata()?.permissions();
+    let mut writer = if resume_from > 0 {
+        // The part already copied is only read when it has to be hashed
+        match hasher.as_mut() {
+            Some(hasher) => {
+                io::copy(&mut (&mut reader).take(resume_from), hasher)?;
+            }
+            None => {
+                reader.seek(SeekFrom::Start(resume_from))?;
+            }
+        }
+        fs::OpenOptions::new().append(true).open(dest)?
+    } else {
+        fs::File::create(dest)?
+    };
+    let mut buffer = vec![0; chunk_size];
+    let mut written: u64 = resume_from;
+
+    loop {
+        if abort.is_some_and(|abort| abort.load(Ordering::SeqCst)) {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "Copy aborted"));
+        }
+
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buffer[..read])?;
+        if let Some(hasher) = hasher.as_mut() {
+            hasher.update(&buffer[..read]);
+        }
+        written += read as u64;
+        if let Some(limiter) = limiter {
+            limiter.consume(read as u64);
+        }
+    }
+
+    writer.flush()?;
+    fs::set_permissions(dest, permissions)?;
+    Ok((written, hasher.map(|hasher| hasher.finalize())))
+}
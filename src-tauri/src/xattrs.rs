@@ -0,0 +1,105 @@
+//! Copying extended attributes on Unix and alternate data streams on Windows,
+//! which carry metadata like Finder tags, SELinux contexts and the download
+//! origin of a file.
+
+use std::path::Path;
+
+/// Copy every extended attribute of `source` onto `dest`, returning a message
+/// for each one that couldn't be copied
+#[cfg(unix)]
+pub(crate) fn copy_extended_attributes(source: &Path, dest: &Path) -> Vec<String> {
+    let names = match xattr::list(source) {
+        Ok(names) => names,
+        Err(e) => return vec![format!("Failed to read extended attributes: {}", e)],
+    };
+
+    let mut failures = Vec::new();
+    for name in names {
+        let result = xattr::get(source, &name).and_then(|value| match value {
+            Some(value) => xattr::set(dest, &name, &value),
+            None => Ok(()),
+        });
+        if let Err(e) = result {
+            failures.push(format!(
+                "Failed to copy extended attribute {}: {}",
+                name.to_string_lossy(),
+                e
+            ));
+        }
+    }
+    failures
+}
+
+/// Copy every alternate data stream of `source` onto `dest`, returning a
+/// message for each one that couldn't be copied
+#[cfg(windows)]
+pub(crate) fn copy_extended_attributes(source: &Path, dest: &Path) -> Vec<String> {
+    use std::ffi::OsString;
+    use std::os::windows::ffi::{OsStrExt, OsStringExt};
+    use windows_sys::Win32::Foundation::INVALID_HANDLE_VALUE;
+    use windows_sys::Win32::Storage::FileSystem::{
+        FindClose, FindFirstStreamW, FindNextStreamW, FindStreamInfoStandard,
+        WIN32_FIND_STREAM_DATA,
+    };
+
+    let wide_source: Vec<u16> = source.as_os_str().encode_wide().chain(Some(0)).collect();
+    // SAFETY: the struct is plain data, for which all zeroes is valid
+    let mut data: WIN32_FIND_STREAM_DATA = unsafe { std::mem::zeroed() };
+    // SAFETY: the path is NUL-terminated and `data` is a stream data struct
+    // that outlives the call
+    let handle = unsafe {
+        FindFirstStreamW(
+            wide_source.as_ptr(),
+            FindStreamInfoStandard,
+            &mut data as *mut WIN32_FIND_STREAM_DATA as *mut _,
+            0,
+        )
+    };
+    // Also returned for file systems without streams, like FAT
+    if handle == INVALID_HANDLE_VALUE {
+        return Vec::new();
+    }
+
+    let mut failures = Vec::new();
+    loop {
+        let len = data.cStreamName.iter().position(|&c| c == 0).unwrap_or(data.cStreamName.len());
+        let name = OsString::from_wide(&data.cStreamName[..len]);
+
+        // The unnamed stream is the file's content, which is already copied
+        if name != "::$DATA" {
+            let mut source_stream = source.as_os_str().to_owned();
+            source_stream.push(&name);
+            let mut dest_stream = dest.as_os_str().to_owned();
+            dest_stream.push(&name);
+
+            let result = std::fs::File::open(&source_stream).and_then(|mut reader| {
+                let mut writer = std::fs::File::create(&dest_stream)?;
+                std::io::copy(&mut reader, &mut writer)
+            });
+            if let Err(e) = result {
+                failures.push(format!(
+                    "Failed to copy alternate data stream {}: {}",
+                    name.to_string_lossy(),
+                    e
+                ));
+            }
+        }
+
+        // SAFETY: `handle` is a valid search handle until closed below
+        let found = unsafe {
+            FindNextStreamW(handle, &mut data as *mut WIN32_FIND_STREAM_DATA as *mut _)
+        };
+        if found == 0 {
+            break;
+        }
+    }
+
+    // SAFETY: `handle` came from `FindFirstStreamW` and is closed only once
+    unsafe { FindClose(handle) };
+    failures
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn copy_extended_attributes(_source: &Path, _dest: &Path) -> Vec<String> {
+    vec!["Extended attributes aren't supported on this platform".to_string()]
+}
@@ -19,13 +19,45 @@ interface BackupProgress {
   copied_count: number;
   skipped_count: number;
   total_count: number;
+  total_bytes: number;
+  copied_bytes: number;
+  verifying: boolean;
+  bytes_per_second: number;
+  eta_seconds: number | null;
+  percent: number;
+  seq: number;
 }
 
 interface BackupComplete {
   success: boolean;
   copied_count: number;
   skipped_count: number;
+  skipped_collision: number;
+  excluded_filter: number;
+  skipped_unreadable: number;
+  left_out_count: number;
   message: string;
+  errors: BackupError[];
+  updated_count: number;
+  deleted_count: number;
+  moved_count: number;
+  reflinked_count: number;
+  deduped_count: number;
+  linked_count: number;
+  copied_files: string[];
+  top_files: [string, number][];
+  per_source: SourceStats[];
+  elapsed_ms: number;
+  average_bytes_per_second: number;
+  seq: number;
+}
+
+interface SourceStats {
+  path: string;
+  copied: number;
+  skipped: number;
+  bytes: number;
+  errors: number;
 }
 
 interface BackupError {
@@ -94,6 +126,7 @@ function FormScreen() {
     respectGitignore,
     includeSourceDir,
     collisionMode,
+    dryRun,
     addSourcePath,
     removeSourcePath,
     clearSourcePaths,
@@ -105,6 +138,7 @@ function FormScreen() {
     setRespectGitignore,
     setIncludeSourceDir,
     setCollisionMode,
+    setDryRun,
     setScreen,
     setProgress,
     setCopiedCount,
@@ -270,6 +304,7 @@ function FormScreen() {
     { value: 'overwrite', label: 'Overwrite' },
     { value: 'skip', label: 'Skip' },
     { value: 'rename', label: 'Rename' },
+    { value: 'newer', label: 'Newer' },
   ] as const;
 
   const startBackup = async () => {
@@ -291,9 +326,39 @@ function FormScreen() {
         sourcePaths,
         targetPath,
         blacklist,
+        whitelist: [],
         respectGitignore,
         includeSourceDir,
         collisionMode,
+        dryRun,
+        preserveTimestamps: true,
+        incremental: false,
+        verify: false,
+        mirror: false,
+        twoPass: false,
+        symlinkMode: "follow",
+        atomic: true,
+        resumePartial: false,
+        failOnExistingForeign: false,
+        sanitizeNames: false,
+        fillMode: false,
+        excludeExtensions: [],
+        useHashCache: false,
+        interactiveFilter: false,
+        preserveCreationTime: false,
+        sync: false,
+        absoluteBlacklist: [],
+        strict: false,
+        moveFiles: false,
+        preservePermissions: true,
+        preserveXattr: false,
+        flatten: false,
+        dedupe: false,
+        deduplicateSymlinks: false,
+        regexBlacklist: [],
+        versioned: false,
+        writeReport: false,
+        copySpecialFiles: false,
       });
     } catch (error) {
       setMessage(`Error: ${error}`);
@@ -453,6 +518,14 @@ function FormScreen() {
           />
           <span>Respect .gitignore</span>
         </label>
+        <label class="checkbox-label">
+          <input
+            type="checkbox"
+            checked={dryRun}
+            onChange={(e) => setDryRun(e.currentTarget.checked)}
+          />
+          <span>Dry run</span>
+        </label>
         <div class="collision-dropdown-wrapper">
           <button
             type="button"
@@ -486,7 +559,7 @@ function FormScreen() {
         onClick={startBackup}
         disabled={sourcePaths.length === 0 || !targetPath}
       >
-        Start Backup
+        {dryRun ? "Start Dry Run" : "Start Backup"}
       </button>
     </div>
   );
@@ -494,6 +567,12 @@ function FormScreen() {
 
 function ProgressScreen() {
   const { progress, currentFile, copiedCount, skippedCount, totalCount } = useBackupStore();
+  const [cancelling, setCancelling] = useState(false);
+
+  const cancelBackup = async () => {
+    setCancelling(true);
+    await invoke("cancel_backup");
+  };
 
   return (
     <div class="screen progress-screen">
@@ -520,6 +599,15 @@ function ProgressScreen() {
           </div>
         </div>
       </div>
+
+      <button
+        type="button"
+        class="action-btn cancel-btn"
+        onClick={cancelBackup}
+        disabled={cancelling}
+      >
+        {cancelling ? "Cancelling..." : "Cancel"}
+      </button>
     </div>
   );
 }
@@ -600,14 +688,13 @@ function App() {
       unlistenProgress = await listen<BackupProgress>(
         "backup-progress",
         (event) => {
-          const { current_file, copied_count, skipped_count, total_count } = event.payload;
+          const { current_file, copied_count, skipped_count, total_count, percent } =
+            event.payload;
           setCurrentFile(current_file);
           setCopiedCount(copied_count);
           setSkippedCount(skipped_count);
           setTotalCount(total_count);
-          setProgress(
-            total_count > 0 ? Math.round(((copied_count + skipped_count) / total_count) * 100) : 0
-          );
+          setProgress(Math.round(percent));
         }
       );
 
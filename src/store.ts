@@ -2,7 +2,7 @@ import { create } from 'zustand';
 import { persist } from 'zustand/middleware';
 
 type Screen = 'form' | 'progress' | 'complete';
-type CollisionMode = 'overwrite' | 'skip' | 'rename';
+type CollisionMode = 'overwrite' | 'skip' | 'rename' | 'newer';
 
 interface BackupState {
   // Screen navigation
@@ -18,6 +18,7 @@ interface BackupState {
   respectGitignore: boolean;
   includeSourceDir: boolean;
   collisionMode: CollisionMode;
+  dryRun: boolean;
 
   addSourcePath: (path: string) => void;
   removeSourcePath: (path: string) => void;
@@ -30,6 +31,7 @@ interface BackupState {
   setRespectGitignore: (value: boolean) => void;
   setIncludeSourceDir: (value: boolean) => void;
   setCollisionMode: (mode: CollisionMode) => void;
+  setDryRun: (value: boolean) => void;
 
   // Progress data
   progress: number;
@@ -69,6 +71,7 @@ const initialState = {
   respectGitignore: false,
   includeSourceDir: true,
   collisionMode: 'overwrite' as CollisionMode,
+  dryRun: false,
   progress: 0,
   currentFile: '',
   currentFileProgress: 0,
@@ -116,6 +119,7 @@ export const useBackupStore = create<BackupState>()(
       setRespectGitignore: (value) => set({ respectGitignore: value }),
       setIncludeSourceDir: (value) => set({ includeSourceDir: value }),
       setCollisionMode: (mode) => set({ collisionMode: mode }),
+      setDryRun: (value) => set({ dryRun: value }),
 
       setProgress: (progress) => set({ progress }),
       setCurrentFile: (file) => set({ currentFile: file }),
@@ -136,6 +140,7 @@ export const useBackupStore = create<BackupState>()(
         respectGitignore: state.respectGitignore,
         includeSourceDir: state.includeSourceDir,
         collisionMode: state.collisionMode,
+        dryRun: state.dryRun,
       })),
     }),
     {
@@ -149,6 +154,7 @@ export const useBackupStore = create<BackupState>()(
         respectGitignore: state.respectGitignore,
         includeSourceDir: state.includeSourceDir,
         collisionMode: state.collisionMode,
+        dryRun: state.dryRun,
       }),
     }
   )